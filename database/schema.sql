@@ -29,3 +29,12 @@ CREATE TABLE games (
 	boardsize SMALLINT DEFAULT 19 NOT NULL,
 	extra JSONB
 );
+CREATE TABLE game_ratings (
+	game INTEGER PRIMARY KEY REFERENCES games (id),
+	whiterating DOUBLE PRECISION NOT NULL,
+	blackrating DOUBLE PRECISION NOT NULL
+);
+CREATE TABLE settings (
+	id INTEGER PRIMARY KEY,
+	extra JSONB
+);
@@ -0,0 +1,396 @@
+//! Rating calculation for a whole series of games, such as a season of a
+//! ladder, independent of how the games are stored
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{Handicap, Rating, RatingSystem};
+
+/// A rated game
+#[derive(Debug, Copy, Clone)]
+pub struct Game<Id> {
+    pub white: Id,
+    pub black: Id,
+    pub handicap: Handicap,
+    pub board_size: u32,
+    /// Result for white: 1 for a win, 0.5 for jigo, 0 for a loss
+    pub white_result: f64,
+}
+
+/// Ratings before a game and the changes caused by it
+#[derive(Debug, Copy, Clone)]
+pub struct GameDelta {
+    pub white_rating: Rating,
+    pub black_rating: Rating,
+    /// Change for white, before the maximum drop of the period is applied
+    pub white_delta: f64,
+    /// Change for black, before the maximum drop of the period is applied
+    pub black_delta: f64,
+}
+
+/// Outcome of a batch rating calculation
+#[derive(Debug)]
+pub struct BatchResult<Id> {
+    /// Final rating of every player
+    pub ratings: HashMap<Id, Rating>,
+    /// Changes for every game, grouped by round in the same order as the
+    /// input games
+    pub games: Vec<Vec<GameDelta>>,
+}
+
+/// Error for a game with a player who has no initial rating
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownPlayer<Id>(pub Id);
+
+impl<Id: fmt::Debug> fmt::Display for UnknownPlayer<Id> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "player {:?} has no initial rating", self.0)
+    }
+}
+
+impl<Id: fmt::Debug> std::error::Error for UnknownPlayer<Id> {}
+
+/// Calculates ratings for games grouped into rounds (tournaments). Within a
+/// round all games are rated with the ratings from before the round, and the
+/// changes are applied together at its end. The rounds can be grouped into
+/// longer periods, in which a player loses at most `max_drop` in total.
+pub struct Batch<'a> {
+    pub system: &'a RatingSystem,
+    /// Number of rated games for which a new player's rating is provisional
+    pub provisional_games: u32,
+    /// Factor applied to rating changes while the rating is provisional
+    pub provisional_factor: f64,
+}
+
+struct PendingRating {
+    rating: Rating,
+    /// Rating at the start of the period for the maximum drop
+    period_start: Rating,
+    pending: f64,
+    /// Number of rated games processed so far
+    games: u32,
+}
+
+impl<'a> Batch<'a> {
+    /// A batch calculation without provisional ratings
+    pub fn new(system: &'a RatingSystem) -> Self {
+        Batch {
+            system,
+            provisional_games: 0,
+            provisional_factor: 1.0,
+        }
+    }
+
+    /// Add the change for a game, taking a provisional rating into account
+    fn add_game(&self, pr: &mut PendingRating, adj: f64) -> f64 {
+        let factor = if pr.games < self.provisional_games {
+            self.provisional_factor
+        } else {
+            1.0
+        };
+        pr.pending += factor * adj;
+        pr.games += 1;
+        factor * adj
+    }
+
+    /// Rate the games of one round and apply the changes at its end
+    fn rate_round<Id>(
+        &self,
+        ratings: &mut HashMap<Id, PendingRating>,
+        round: &[Game<Id>],
+    ) -> Vec<GameDelta>
+    where
+        Id: Copy + Eq + Hash,
+    {
+        let sys = self.system;
+        let mut round_deltas = Vec::with_capacity(round.len());
+        for game in round {
+            let white_rating = ratings[&game.white].rating;
+            let black_rating = ratings[&game.black].rating;
+            let handicap = sys.board_handicap(game.handicap.effective(), game.board_size);
+            let weight = sys.board_weight(game.board_size);
+            let wadj =
+                sys.rating_adjustment(white_rating, black_rating, -handicap, game.white_result);
+            let badj = sys.rating_adjustment(
+                black_rating,
+                white_rating,
+                handicap,
+                1.0 - game.white_result,
+            );
+            let white_delta = self.add_game(ratings.get_mut(&game.white).unwrap(), weight * wadj);
+            let black_delta = self.add_game(ratings.get_mut(&game.black).unwrap(), weight * badj);
+            round_deltas.push(GameDelta {
+                white_rating,
+                black_rating,
+                white_delta,
+                black_delta,
+            });
+        }
+        for pr in ratings.values_mut() {
+            let floor = pr.period_start.0 - sys.max_drop;
+            let pending = std::mem::replace(&mut pr.pending, 0.0);
+            let adj = f64::max(pending, floor - pr.rating.0);
+            pr.rating = sys.adjust_rating(pr.rating, adj);
+        }
+        round_deltas
+    }
+
+    /// Rate the games, which are grouped by round in chronological order,
+    /// with the maximum drop applying to each round. Every player that takes
+    /// part in a game must have an initial rating, otherwise nothing is rated
+    /// and the first unknown player is returned.
+    /// ```
+    /// use gorating::batch::{Batch, Game};
+    /// use gorating::{Handicap, Rating, RatingSystem};
+    /// let sys = RatingSystem::new();
+    /// let game = Game {
+    ///     white: "alice",
+    ///     black: "bob",
+    ///     handicap: Handicap::new(0.0),
+    ///     board_size: 19,
+    ///     white_result: 1.0,
+    /// };
+    /// let result = Batch::new(&sys).rate(
+    ///     vec![("alice", Rating(1500.0)), ("bob", Rating(1500.0))],
+    ///     &[vec![game]],
+    /// )
+    /// .unwrap();
+    /// assert!(result.ratings["alice"].0 > 1500.0);
+    /// assert!(result.games[0][0].black_delta < 0.0);
+    /// ```
+    pub fn rate<Id, I>(
+        &self,
+        initial_ratings: I,
+        rounds: &[Vec<Game<Id>>],
+    ) -> Result<BatchResult<Id>, UnknownPlayer<Id>>
+    where
+        Id: Copy + Eq + Hash,
+        I: IntoIterator<Item = (Id, Rating)>,
+    {
+        let periods: Vec<Vec<Vec<Game<Id>>>> =
+            rounds.iter().map(|round| vec![round.clone()]).collect();
+        self.rate_periods(initial_ratings, &periods)
+    }
+
+    /// Rate the games, which are grouped by the period for the maximum drop
+    /// and within it by round, in chronological order. The ratings change
+    /// after every round, but in total a player cannot lose more than
+    /// `max_drop` from the rating at the start of the period.
+    /// ```
+    /// use gorating::batch::{Batch, Game};
+    /// use gorating::{Handicap, Rating, RatingSystem};
+    /// let sys = RatingSystem {
+    ///     max_drop: 10.0,
+    ///     ..RatingSystem::new()
+    /// };
+    /// let loss = Game {
+    ///     white: "alice",
+    ///     black: "bob",
+    ///     handicap: Handicap::new(0.0),
+    ///     board_size: 19,
+    ///     white_result: 0.0,
+    /// };
+    /// let result = Batch::new(&sys)
+    ///     .rate_periods(
+    ///         vec![("alice", Rating(1500.0)), ("bob", Rating(1500.0))],
+    ///         &[vec![vec![loss], vec![loss]]],
+    ///     )
+    ///     .unwrap();
+    /// // The second round is rated with the ratings after the first one.
+    /// assert!(result.games[1][0].white_rating.0 < 1500.0);
+    /// assert_eq!(result.ratings["alice"].0, 1490.0);
+    /// ```
+    pub fn rate_periods<Id, I>(
+        &self,
+        initial_ratings: I,
+        periods: &[Vec<Vec<Game<Id>>>],
+    ) -> Result<BatchResult<Id>, UnknownPlayer<Id>>
+    where
+        Id: Copy + Eq + Hash,
+        I: IntoIterator<Item = (Id, Rating)>,
+    {
+        let mut ratings: HashMap<Id, PendingRating> = initial_ratings
+            .into_iter()
+            .map(|(id, rating)| {
+                let pr = PendingRating {
+                    rating,
+                    period_start: rating,
+                    pending: 0.0,
+                    games: 0,
+                };
+                (id, pr)
+            })
+            .collect();
+        for game in periods.iter().flatten().flatten() {
+            for id in [game.white, game.black] {
+                if !ratings.contains_key(&id) {
+                    return Err(UnknownPlayer(id));
+                }
+            }
+        }
+        let mut deltas = Vec::new();
+        for period in periods {
+            for pr in ratings.values_mut() {
+                pr.period_start = pr.rating;
+            }
+            for round in period {
+                deltas.push(self.rate_round(&mut ratings, round));
+            }
+        }
+        Ok(BatchResult {
+            ratings: ratings
+                .into_iter()
+                .map(|(id, pr)| (id, pr.rating))
+                .collect(),
+            games: deltas,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game(white: u32, black: u32, white_result: f64) -> Game<u32> {
+        Game {
+            white,
+            black,
+            handicap: Handicap::new(0.0),
+            board_size: 19,
+            white_result,
+        }
+    }
+
+    fn initial() -> Vec<(u32, Rating)> {
+        vec![
+            (1, Rating(1000.0)),
+            (2, Rating(1000.0)),
+            (3, Rating(1000.0)),
+        ]
+    }
+
+    #[test]
+    fn test_batch_empty() {
+        let sys = RatingSystem::new();
+        let result = Batch::new(&sys).rate(initial(), &[]).unwrap();
+        assert_eq!(result.ratings.len(), 3);
+        assert_eq!(result.ratings[&1].0, 1000.0);
+        assert!(result.games.is_empty());
+    }
+
+    #[test]
+    fn test_batch_unknown_player() {
+        let sys = RatingSystem::new();
+        let result =
+            Batch::new(&sys).rate(initial(), &[vec![game(1, 2, 1.0)], vec![game(3, 4, 0.0)]]);
+        assert_eq!(result.unwrap_err(), UnknownPlayer(4));
+        assert_eq!(
+            UnknownPlayer(4).to_string(),
+            "player 4 has no initial rating"
+        );
+    }
+
+    #[test]
+    fn test_batch_periods() {
+        let sys = RatingSystem::new();
+        let result = Batch::new(&sys)
+            .rate(
+                initial(),
+                &[
+                    vec![game(1, 2, 1.0), game(1, 3, 1.0)],
+                    vec![game(2, 3, 0.5)],
+                ],
+            )
+            .unwrap();
+        let adjw = sys.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 1.0);
+        let adjl = sys.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 0.0);
+        // Both games in the first period are rated with the initial ratings.
+        let first = &result.games[0];
+        assert_eq!(first[1].white_rating.0, 1000.0);
+        assert_eq!(first[0].white_delta, adjw);
+        assert_eq!(first[1].white_delta, adjw);
+        assert_eq!(result.ratings[&1].0, 1000.0 + 2.0 * adjw);
+        // The second period uses the ratings after the first.
+        let second = &result.games[1][0];
+        assert_eq!(second.white_rating.0, 1000.0 + adjl);
+        assert_eq!(result.ratings[&2].0, 1000.0 + adjl + second.white_delta);
+        assert_eq!(result.ratings[&3].0, 1000.0 + adjl + second.black_delta);
+    }
+
+    #[test]
+    fn test_batch_max_drop() {
+        let sys = RatingSystem {
+            max_drop: 10.0,
+            ..RatingSystem::new()
+        };
+        let result = Batch::new(&sys)
+            .rate(initial(), &[vec![game(1, 2, 1.0), game(3, 2, 1.0)]])
+            .unwrap();
+        let deltas = &result.games[0];
+        assert!(deltas[0].black_delta + deltas[1].black_delta < -10.0);
+        assert_eq!(result.ratings[&2].0, 990.0);
+    }
+
+    #[test]
+    fn test_batch_max_drop_period() {
+        let rounds = [vec![game(1, 2, 1.0)], vec![game(3, 2, 1.0)]];
+        let periods = [rounds.to_vec()];
+        // Without a binding limit, the period only affects the limit.
+        let sys = RatingSystem::new();
+        let per_round = Batch::new(&sys).rate(initial(), &rounds).unwrap();
+        let per_period = Batch::new(&sys).rate_periods(initial(), &periods).unwrap();
+        for id in 1..=3 {
+            assert_eq!(per_round.ratings[&id].0, per_period.ratings[&id].0);
+        }
+        let second = per_period.games[1][0].black_rating.0;
+        assert_eq!(second, per_round.games[1][0].black_rating.0);
+        assert!(second < 1000.0);
+        // Otherwise the losses in both rounds are limited together.
+        let sys = RatingSystem {
+            max_drop: 10.0,
+            ..RatingSystem::new()
+        };
+        let per_round = Batch::new(&sys).rate(initial(), &rounds).unwrap();
+        assert_eq!(per_round.ratings[&2].0, 980.0);
+        let per_period = Batch::new(&sys).rate_periods(initial(), &periods).unwrap();
+        assert_eq!(per_period.ratings[&2].0, 990.0);
+        assert_eq!(per_period.games[1][0].black_rating.0, 990.0);
+    }
+
+    #[test]
+    fn test_batch_provisional() {
+        let sys = RatingSystem::new();
+        let batch = Batch {
+            provisional_games: 1,
+            provisional_factor: 3.0,
+            ..Batch::new(&sys)
+        };
+        let result = batch
+            .rate(initial(), &[vec![game(1, 2, 1.0)], vec![game(1, 3, 1.0)]])
+            .unwrap();
+        let first = &result.games[0][0];
+        let second = &result.games[1][0];
+        let adjw = sys.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 1.0);
+        assert_eq!(first.white_delta, 3.0 * adjw);
+        // Player 1 is no longer provisional, player 3 still is.
+        let adj1 = sys.rating_adjustment(second.white_rating, Rating(1000.0), 0.0, 1.0);
+        let adj3 = sys.rating_adjustment(Rating(1000.0), second.white_rating, 0.0, 0.0);
+        assert_eq!(second.white_delta, adj1);
+        assert_eq!(second.black_delta, 3.0 * adj3);
+    }
+
+    #[test]
+    fn test_batch_board_size() {
+        let sys = RatingSystem::new();
+        let mut small = game(1, 2, 1.0);
+        small.board_size = 9;
+        let result = Batch::new(&sys)
+            .rate(initial(), &[vec![game(1, 2, 1.0)], vec![small]])
+            .unwrap();
+        let full = result.games[0][0].white_delta;
+        let small = result.games[1][0];
+        let adj = sys.rating_adjustment(small.white_rating, small.black_rating, 0.0, 1.0);
+        assert_eq!(small.white_delta, sys.board_weight(9) * adj);
+        assert!(small.white_delta < full);
+    }
+}
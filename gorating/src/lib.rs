@@ -1,3 +1,7 @@
+pub mod batch;
+#[cfg(feature = "serde")]
+pub mod serialization;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Rating(pub f64);
 
@@ -25,9 +29,101 @@ impl Rating {
     }
 }
 
+/// Handicap of a game, stored in the compact encoding used in the database:
+///
+/// * `0.0` is an even game with 6.5 komi for white,
+/// * `1.0` is no handicap stones and no komi,
+/// * `1.5` is no handicap stones and 0.5 komi for black,
+/// * `n` (n >= 2) is n handicap stones and no komi,
+/// * `n + 0.5` (n >= 2) is n handicap stones and 0.5 komi for black.
+///
+/// Any other combination of stones and komi is stored as a negative number,
+/// see [`HandicapDetails`].
 #[derive(Debug, Copy, Clone)]
 pub struct Handicap(f64);
 
+/// Colour of a player
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Colour {
+    Black,
+    White,
+}
+
+/// Handicap as the number of stones and the komi. A single handicap stone is
+/// the same as none (black moves first), so it is stored as zero stones.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HandicapDetails {
+    pub stones: u32,
+    /// Komi in points, a non-negative multiple of 0.5
+    pub komi: f64,
+    /// The player who receives the komi; white if there is no komi
+    pub komi_colour: Colour,
+}
+
+/// Largest number of stones and komi that can be stored in a `Handicap`
+const MAX_STONES: u32 = 999;
+const MAX_KOMI: f64 = 249.5;
+
+impl HandicapDetails {
+    /// Create handicap details, normalizing one stone to zero stones and
+    /// zero komi to white's komi. Returns `None` if the values cannot be
+    /// stored in a `Handicap`.
+    /// ```
+    /// use gorating::{Colour, HandicapDetails};
+    /// let d = HandicapDetails::new(3, 0.5, Colour::White).unwrap();
+    /// assert_eq!(d.stones, 3);
+    /// assert!(HandicapDetails::new(0, 6.4, Colour::White).is_none());
+    /// ```
+    pub fn new(stones: u32, komi: f64, komi_colour: Colour) -> Option<Self> {
+        if stones > MAX_STONES || !(0.0..=MAX_KOMI).contains(&komi) || (komi * 2.0).fract() != 0.0 {
+            return None;
+        }
+        Some(Self {
+            stones: if stones == 1 { 0 } else { stones },
+            komi,
+            komi_colour: if komi == 0.0 {
+                Colour::White
+            } else {
+                komi_colour
+            },
+        })
+    }
+
+    /// Komi for white, negative if black receives the komi
+    fn white_komi(&self) -> f64 {
+        match self.komi_colour {
+            Colour::White => self.komi,
+            Colour::Black => -self.komi,
+        }
+    }
+}
+
+impl From<HandicapDetails> for Handicap {
+    /// Convert handicap details to the compact encoding.
+    /// ```
+    /// use gorating::{Colour, Handicap, HandicapDetails};
+    /// let d = HandicapDetails::new(3, 0.5, Colour::Black).unwrap();
+    /// assert_eq!(Handicap::from(d).to_f64(), 3.5);
+    /// ```
+    fn from(details: HandicapDetails) -> Self {
+        let stones = f64::from(details.stones);
+        let white_komi = details.white_komi();
+        Handicap(if stones == 0.0 && white_komi == 6.5 {
+            0.0
+        } else if stones == 0.0 && white_komi == 0.0 {
+            1.0
+        } else if stones == 0.0 && white_komi == -0.5 {
+            1.5
+        } else if stones >= 2.0 && white_komi == 0.0 {
+            stones
+        } else if stones >= 2.0 && white_komi == -0.5 {
+            stones + 0.5
+        } else {
+            -(stones * 1000.0 + 500.0 + white_komi)
+        })
+    }
+}
+
 impl std::fmt::Display for Handicap {
     /// Format handicap value as handicap stones and komi
     /// ```
@@ -41,6 +137,18 @@ impl std::fmt::Display for Handicap {
             write!(formatter, "0b0")
         } else if self.0 == 1.5 {
             write!(formatter, "0b5")
+        } else if self.0 < 0.0 {
+            match self.details() {
+                Some(HandicapDetails {
+                    stones: 0,
+                    komi,
+                    komi_colour: Colour::Black,
+                }) => write!(formatter, "reverse komi {}", komi),
+                Some(d) => write!(formatter, "H{} K{}", d.stones, d.white_komi()),
+                None => write!(formatter, "{}", self.0),
+            }
+        } else if self.0 < 2.0 {
+            write!(formatter, "{}", self.0)
         } else if self.0.round() == self.0 {
             write!(formatter, "{}b0", self.0)
         } else if (self.0 - 0.5).round() == self.0 - 0.5 {
@@ -55,10 +163,55 @@ impl std::fmt::Display for Handicap {
 /// Error parsing a handicap value from a string
 pub struct BadHandicap;
 
+/// Parse a komi value, which must be a multiple of 0.5
+fn parse_komi(s: &str) -> Result<f64, BadHandicap> {
+    let komi: f64 = match s.strip_suffix('½') {
+        Some(whole) => whole.parse::<f64>().map_err(|_| BadHandicap)? + 0.5,
+        None => s.parse().map_err(|_| BadHandicap)?,
+    };
+    if komi.is_finite() && (komi * 2.0).fract() == 0.0 {
+        Ok(komi)
+    } else {
+        Err(BadHandicap)
+    }
+}
+
+/// Parse the notations "H3 K0.5" (negative komi is for black), "H3", "K7.5"
+/// and "reverse komi 5.5"
+fn parse_details(s: &str) -> Result<HandicapDetails, BadHandicap> {
+    let s = s.to_ascii_lowercase();
+    if let Some(komi) = s.strip_prefix("reverse komi") {
+        let komi = parse_komi(komi.trim())?;
+        return HandicapDetails::new(0, komi, Colour::Black).ok_or(BadHandicap);
+    }
+    let mut stones = None;
+    let mut komi = None;
+    for word in s.split_whitespace() {
+        if let (Some(n), None) = (word.strip_prefix('h'), stones) {
+            stones = Some(n.parse::<u32>().map_err(|_| BadHandicap)?);
+        } else if let (Some(k), None) = (word.strip_prefix('k'), komi) {
+            komi = Some(parse_komi(k)?);
+        } else {
+            return Err(BadHandicap);
+        }
+    }
+    if stones.is_none() && komi.is_none() {
+        return Err(BadHandicap);
+    }
+    let komi = komi.unwrap_or(0.0);
+    let colour = if komi < 0.0 {
+        Colour::Black
+    } else {
+        Colour::White
+    };
+    HandicapDetails::new(stones.unwrap_or(0), komi.abs(), colour).ok_or(BadHandicap)
+}
+
 impl std::str::FromStr for Handicap {
     type Err = BadHandicap;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
         Ok(Handicap::new(if s == "0w6½" || s == "0w6.5" {
             0.0
         } else if s == "0b0" || s == "0w0" {
@@ -70,12 +223,40 @@ impl std::str::FromStr for Handicap {
         } else if s.ends_with("b5") {
             let x: f64 = s[..s.len() - 2].parse().map_err(|_| BadHandicap)?;
             x + 0.5
+        } else if s.starts_with(|c: char| c.is_ascii_digit()) {
+            let x: f64 = s.parse().map_err(|_| BadHandicap)?;
+            if (x * 2.0).fract() != 0.0 {
+                return Err(BadHandicap);
+            }
+            x
         } else {
-            s.parse().map_err(|_| BadHandicap)?
+            return Ok(Handicap::from(parse_details(s)?));
         }))
     }
 }
 
+/// Black's advantage in ranks, as used by `RatingSystem::expected_result`,
+/// for an advantage of `points` relative to an even game without komi. The
+/// standard combinations are fixed points: 6.5 komi for white is no
+/// advantage, no komi is half a rank, 0.5 komi for black is one rank, and
+/// each further stone adds one rank to the last two. In between, the ranks
+/// are interpolated linearly.
+fn advantage_ranks(points: f64) -> f64 {
+    if points <= 0.0 {
+        (points + 6.5) / 13.0
+    } else if points <= 0.5 {
+        0.5 + points
+    } else {
+        let stones = ((points - 0.5) / 13.0).floor();
+        let rest = points - 0.5 - 13.0 * stones;
+        if rest <= 12.5 {
+            1.0 + stones + rest / 25.0
+        } else {
+            1.5 + stones + (rest - 12.5)
+        }
+    }
+}
+
 impl Handicap {
     #[inline]
     pub fn new(x: f64) -> Self {
@@ -86,27 +267,200 @@ impl Handicap {
     pub fn to_f64(self) -> f64 {
         self.0
     }
+
+    /// Decode the number of stones and the komi, or `None` for values that
+    /// do not correspond to any combination.
+    /// ```
+    /// use gorating::{Colour, Handicap};
+    /// let d = Handicap::new(0.0).details().unwrap();
+    /// assert_eq!((d.stones, d.komi, d.komi_colour), (0, 6.5, Colour::White));
+    /// ```
+    pub fn details(self) -> Option<HandicapDetails> {
+        let x = self.0;
+        let (stones, white_komi) = if x < 0.0 {
+            let code = -x - 500.0;
+            let stones = ((code + 500.0) / 1000.0).floor();
+            (stones, code - stones * 1000.0)
+        } else if x == 0.0 {
+            (0.0, 6.5)
+        } else if x == 1.0 {
+            (0.0, 0.0)
+        } else if x == 1.5 {
+            (0.0, -0.5)
+        } else if x >= 2.0 && x.fract() == 0.0 {
+            (x, 0.0)
+        } else if x >= 2.0 && x.fract() == 0.5 {
+            (x - 0.5, -0.5)
+        } else {
+            return None;
+        };
+        if stones > f64::from(MAX_STONES) {
+            return None;
+        }
+        let colour = if white_komi < 0.0 {
+            Colour::Black
+        } else {
+            Colour::White
+        };
+        HandicapDetails::new(stones as u32, white_komi.abs(), colour)
+    }
+
+    /// The value of the handicap for rating calculations, in the same units
+    /// as the compact encoding: positive if black has the advantage and
+    /// negative if white has it. For the standard combinations this is the
+    /// compact encoding itself. Other combinations are interpolated between
+    /// the standard ones by black's advantage in points, counting each stone
+    /// after the first as 13 points, so that a bigger handicap always has a
+    /// bigger value.
+    /// ```
+    /// use gorating::Handicap;
+    /// assert_eq!(Handicap::new(2.5).effective(), 2.5);
+    /// let h: Handicap = "H2 K0".parse().unwrap();
+    /// assert_eq!(h.effective(), 2.0);
+    /// ```
+    pub fn effective(self) -> f64 {
+        if self.0 >= 0.0 {
+            return self.0;
+        }
+        let details = match self.details() {
+            Some(details) => details,
+            None => return 0.0,
+        };
+        let points = 13.0 * f64::from(details.stones.saturating_sub(1)) - details.white_komi();
+        let ranks = advantage_ranks(points);
+        if ranks > 0.0 {
+            ranks + 0.5
+        } else if ranks < 0.0 {
+            ranks - 0.5
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Formula used to calculate rating changes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Formula {
+    /// The formula used by the EGD since 2021, which was also applied
+    /// retroactively to older tournaments. This was the only formula before
+    /// the choice of formula was added, and remains the default.
+    Egd2021,
+    /// The formula used by the EGD before 2021, with a tabulated `con` and a
+    /// fixed `epsilon`
+    EgdLegacy,
+}
+
+/// Magnitude of the change in the legacy formula, interpolated linearly
+const LEGACY_CON: [(f64, f64); 27] = [
+    (100.0, 116.0),
+    (200.0, 110.0),
+    (300.0, 105.0),
+    (400.0, 100.0),
+    (500.0, 95.0),
+    (600.0, 90.0),
+    (700.0, 85.0),
+    (800.0, 80.0),
+    (900.0, 75.0),
+    (1000.0, 70.0),
+    (1100.0, 65.0),
+    (1200.0, 60.0),
+    (1300.0, 55.0),
+    (1400.0, 51.0),
+    (1500.0, 47.0),
+    (1600.0, 43.0),
+    (1700.0, 39.0),
+    (1800.0, 35.0),
+    (1900.0, 31.0),
+    (2000.0, 27.0),
+    (2100.0, 24.0),
+    (2200.0, 21.0),
+    (2300.0, 18.0),
+    (2400.0, 15.0),
+    (2500.0, 13.0),
+    (2600.0, 11.0),
+    (2700.0, 10.0),
+];
+
+/// Part of the expected result that neither player gets in the legacy formula
+const LEGACY_EPSILON: f64 = 0.016;
+
+/// Rules for deriving the handicap of a game from the rating difference
+#[derive(Debug, Copy, Clone)]
+pub struct HandicapPolicy {
+    /// Number of stones by which the handicap is reduced
+    pub reduction: f64,
+    /// Maximum number of handicap stones
+    pub max_stones: Option<u32>,
+    /// Games are even if the weaker player has at least this rating
+    pub no_handicap_above: Option<Rating>,
+}
+
+impl HandicapPolicy {
+    /// Full handicap according to the rating difference, without limits
+    pub const fn full() -> Self {
+        Self {
+            reduction: 0.0,
+            max_stones: None,
+            no_handicap_above: None,
+        }
+    }
+}
+
+impl Default for HandicapPolicy {
+    fn default() -> Self {
+        Self::full()
+    }
 }
 
 pub struct RatingSystem {
+    pub formula: Formula,
     pub bonus_factor: f64,
     pub min_rating: Rating,
-    /// Maximum rating points that a player can lose in one tournament
+    /// Maximum rating points that a player can lose in one tournament, or in
+    /// whatever period the caller groups games into when applying changes
     pub max_drop: f64,
 }
 
 impl RatingSystem {
+    /// The rating system as used by the EGD since 2021.
     pub const fn new() -> Self {
         Self {
+            formula: Formula::Egd2021,
             bonus_factor: 0.2,
             min_rating: Rating(-900.0),
             max_drop: 100.0,
         }
     }
 
+    /// The rating system as used by the EGD before 2021, which had no bonus
+    /// term, no ratings below 100 and no limit on the loss per tournament.
+    pub const fn egd_legacy() -> Self {
+        Self {
+            formula: Formula::EgdLegacy,
+            bonus_factor: 0.0,
+            min_rating: Rating(100.0),
+            max_drop: f64::INFINITY,
+        }
+    }
+
     /// Magnitude of the change
     fn con(&self, rating: f64) -> f64 {
-        ((3300.0 - rating) / 200.0).powf(1.6)
+        match self.formula {
+            Formula::Egd2021 => ((3300.0 - rating) / 200.0).powf(1.6),
+            Formula::EgdLegacy => {
+                let (first_rating, first_con) = LEGACY_CON[0];
+                if rating <= first_rating {
+                    return first_con;
+                }
+                for w in LEGACY_CON.windows(2) {
+                    let ((r0, c0), (r1, c1)) = (w[0], w[1]);
+                    if rating <= r1 {
+                        return c0 + (c1 - c0) * (rating - r0) / (r1 - r0);
+                    }
+                }
+                LEGACY_CON[LEGACY_CON.len() - 1].1
+            }
+        }
     }
 
     /// Mapping function to make ratings correspond to ranks (handicap stones)
@@ -114,6 +468,42 @@ impl RatingSystem {
         -7.0 * (3300.0 - rating).ln()
     }
 
+    /// Expected result for the player with rating `r1` against the player
+    /// with rating `r2`, where the ratings have already been corrected for
+    /// the handicap
+    fn expected(&self, r1: f64, r2: f64) -> f64 {
+        match self.formula {
+            Formula::Egd2021 => 1.0 / (1.0 + (self.beta(r2) - self.beta(r1)).exp()),
+            Formula::EgdLegacy => {
+                // The spread depends on the rating of the weaker player.
+                let a = 205.0 - f64::min(r1, r2).clamp(100.0, 2700.0) / 20.0;
+                1.0 / (((r2 - r1) / a).exp() + 1.0) - LEGACY_EPSILON / 2.0
+            }
+        }
+    }
+
+    /// Calculate the expected result (win probability) of a game for the
+    /// player with `rating`. The handicap is positive if the player takes
+    /// black and receives the handicap, and negative if the player gives it.
+    /// ```
+    /// use gorating::{Rating, RatingSystem};
+    /// let sys = RatingSystem::new();
+    /// let p = sys.expected_result(Rating(1500.0), Rating(1500.0), 0.0);
+    /// assert_eq!(p, 0.5);
+    /// ```
+    pub fn expected_result(&self, rating: Rating, other_rating: Rating, handicap: f64) -> f64 {
+        let Rating(mut r1) = rating;
+        let Rating(mut r2) = other_rating;
+        // Increase black's rating depending on the handicap for the
+        // win probability calculation (but not for bonus and con).
+        if handicap > 0.0 {
+            r1 += 100.0 * (handicap - 0.5);
+        } else if handicap < 0.0 {
+            r2 += 100.0 * (-handicap - 0.5);
+        }
+        self.expected(r1, r2)
+    }
+
     pub fn rating_adjustment(
         &self,
         rating: Rating,
@@ -122,22 +512,58 @@ impl RatingSystem {
         result: f64,
     ) -> f64 {
         assert!(result >= 0.0 && result <= 1.0);
+        let expected_result = self.expected_result(rating, other_rating, handicap);
+        let bonus = self.bonus(rating);
         let Rating(rating) = rating;
-        let Rating(other_rating) = other_rating;
-        let mut r1 = rating;
-        let mut r2 = other_rating;
-        // Increase black's rating depending on the handicap for the
-        // win probability calculation (but not for bonus and con).
-        if handicap > 0.0 {
-            r1 += 100.0 * (handicap - 0.5);
-        } else if handicap < 0.0 {
-            r2 += 100.0 * (-handicap - 0.5);
-        }
-        let expected_result = 1.0 / (1.0 + (self.beta(r2) - self.beta(r1)).exp());
-        let bonus = ((2300.0 - rating) / 80.0).exp().ln_1p() * self.bonus_factor;
         self.con(rating) * (result - expected_result) + bonus
     }
 
+    /// Calculate the performance rating for a series of games: the rating
+    /// at which the expected results add up to the actual score. Each game
+    /// is given as the opponent's rating, the handicap as for
+    /// `expected_result` and the result. There is no performance rating if
+    /// all games were won or all were lost.
+    /// ```
+    /// use gorating::{Rating, RatingSystem};
+    /// let sys = RatingSystem::new();
+    /// let games = [(Rating(1500.0), 0.0, 1.0), (Rating(1500.0), 0.0, 0.0)];
+    /// let r = sys.performance_rating(&games).unwrap();
+    /// assert!((r.0 - 1500.0).abs() < 0.01);
+    /// assert!(sys.performance_rating(&games[..1]).is_none());
+    /// ```
+    pub fn performance_rating(&self, games: &[(Rating, f64, f64)]) -> Option<Rating> {
+        let score: f64 = games.iter().map(|&(_, _, result)| result).sum();
+        if score <= 0.0 || score >= games.len() as f64 {
+            return None;
+        }
+        let expected = |rating: f64| -> f64 {
+            games
+                .iter()
+                .map(|&(other, handicap, _)| self.expected_result(Rating(rating), other, handicap))
+                .sum()
+        };
+        // The expected score grows with the rating, which has to stay below
+        // 3300 after the correction for the handicap.
+        let max_handicap = games.iter().map(|&(_, h, _)| h).fold(0.0, f64::max);
+        let mut low = -3000.0;
+        let mut high = 3299.0 - 100.0 * max_handicap;
+        while high - low > 0.01 {
+            let mid = (low + high) / 2.0;
+            if expected(mid) < score {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Some(Rating((low + high) / 2.0))
+    }
+
+    /// Calculate the bonus that a player with `rating` receives for every
+    /// game, regardless of the result, to counter deflation.
+    pub fn bonus(&self, Rating(rating): Rating) -> f64 {
+        ((2300.0 - rating) / 80.0).exp().ln_1p() * self.bonus_factor
+    }
+
     pub fn adjust_rating(&self, rating: Rating, adj: f64) -> Rating {
         let Rating(rating) = rating;
         Rating(f64::max(rating + adj, self.min_rating.0))
@@ -158,6 +584,79 @@ impl RatingSystem {
             (unrounded * 2.0).round() * 0.5
         })
     }
+
+    /// Calculate the handicap for a given (positive) rating difference on a
+    /// board of the given size. On smaller boards, each handicap stone
+    /// makes up for a larger difference in strength.
+    /// ```
+    /// let sys = gorating::RatingSystem::new();
+    /// let h = sys.calculate_handicap_on_board(600.0, 9);
+    /// assert_eq!(h.to_string(), "0b5");
+    /// ```
+    pub fn calculate_handicap_on_board(&self, rating_diff: f64, board_size: u32) -> Handicap {
+        self.calculate_handicap(rating_diff / stone_ranks(board_size))
+    }
+
+    /// Calculate the handicap for a game between players with the given
+    /// ratings on a board of the given size, according to `policy`.
+    /// ```
+    /// use gorating::{HandicapPolicy, Rating, RatingSystem};
+    /// let sys = RatingSystem::new();
+    /// let policy = HandicapPolicy {
+    ///     reduction: 1.0,
+    ///     ..HandicapPolicy::full()
+    /// };
+    /// let h = sys.calculate_handicap_with_policy(Rating(1500.0), Rating(1800.0), 19, &policy);
+    /// assert_eq!(h.to_string(), "2b5");
+    /// ```
+    pub fn calculate_handicap_with_policy(
+        &self,
+        weaker: Rating,
+        stronger: Rating,
+        board_size: u32,
+        policy: &HandicapPolicy,
+    ) -> Handicap {
+        if let Some(limit) = policy.no_handicap_above {
+            if weaker.0 >= limit.0 {
+                return Handicap::new(0.0);
+            }
+        }
+        let diff = (stronger - weaker) / stone_ranks(board_size) - 100.0 * policy.reduction;
+        let handicap = self.calculate_handicap(f64::max(diff, 0.0));
+        match policy.max_stones {
+            Some(0) if handicap.0 > 0.0 => Handicap::new(0.0),
+            Some(1) if handicap.0 > 1.0 => Handicap::new(1.0),
+            Some(max) if handicap.0 > f64::from(max) => Handicap::new(f64::from(max)),
+            _ => handicap,
+        }
+    }
+
+    /// Convert a handicap (which may be negative, as for
+    /// `rating_adjustment`) on a board of the given size to the handicap
+    /// with the same value on a 19×19 board.
+    pub fn board_handicap(&self, handicap: f64, board_size: u32) -> f64 {
+        let k = stone_ranks(board_size);
+        if handicap > 0.0 {
+            0.5 + (handicap - 0.5) * k
+        } else if handicap < 0.0 {
+            -0.5 + (handicap + 0.5) * k
+        } else {
+            0.0
+        }
+    }
+
+    /// Factor for the rating changes of a game on a board of the given size,
+    /// so that games on smaller boards have less effect on ratings.
+    pub fn board_weight(&self, board_size: u32) -> f64 {
+        let ratio = f64::from(board_size) / 19.0;
+        f64::min(ratio * ratio, 1.0)
+    }
+}
+
+/// Number of ranks that one handicap stone is worth on a board of the given
+/// size: 1 on 19×19, about 2.5 on 13×13 and about 6 on 9×9.
+fn stone_ranks(board_size: u32) -> f64 {
+    f64::max((19.0 / f64::from(board_size)).powf(2.4), 1.0)
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -173,6 +672,41 @@ impl Rank {
     pub fn from_rating(Rating(rating): Rating) -> Self {
         Self(rating)
     }
+
+    /// Convert a rank to the rating in the middle of its range.
+    /// ```
+    /// use gorating::{Rank, Rating};
+    /// let r = Rank::from_rating(Rating(1130.0));
+    /// assert_eq!(r.to_rating().0, 1100.0);
+    /// ```
+    pub fn to_rating(self) -> Rating {
+        Rating((self.0 / 100.0).round() * 100.0)
+    }
+}
+
+#[derive(Debug)]
+/// Error parsing a rank from a string
+pub struct BadRank;
+
+impl std::str::FromStr for Rank {
+    type Err = BadRank;
+
+    /// Parse a rank such as "5k", "5 kyu", "2d" or "2 dan".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let split = s.find(|c: char| !c.is_ascii_digit()).ok_or(BadRank)?;
+        let (number, unit) = s.split_at(split);
+        let number: u32 = number.parse().map_err(|_| BadRank)?;
+        match unit.trim_start() {
+            "k" | "kyu" if (1..=30).contains(&number) => {
+                Ok(Rank((21.0 - f64::from(number)) * 100.0))
+            }
+            "d" | "dan" if (1..=9).contains(&number) => {
+                Ok(Rank((20.0 + f64::from(number)) * 100.0))
+            }
+            _ => Err(BadRank),
+        }
+    }
 }
 
 impl std::fmt::Display for Rank {
@@ -186,6 +720,81 @@ impl std::fmt::Display for Rank {
     }
 }
 
+/// Scale on which ranks are named. Ratings always use the EGD scale; the
+/// other scales are approximations that shift the ranks by a whole number of
+/// grades, so that an EGD 1k is about an AGA 1d and an EGD 2k about a KGS 1d.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RankScale {
+    #[default]
+    Egd,
+    Aga,
+    Kgs,
+}
+
+impl RankScale {
+    /// Number of rating points added to a rating to find the EGD rank with
+    /// the same name
+    fn offset(self) -> f64 {
+        match self {
+            RankScale::Egd => 0.0,
+            RankScale::Aga => 100.0,
+            RankScale::Kgs => 200.0,
+        }
+    }
+
+    /// Rank of a rating on this scale.
+    /// ```
+    /// use gorating::{RankScale, Rating};
+    /// assert_eq!(RankScale::Egd.rank(Rating(2000.0)).to_string(), "1k");
+    /// assert_eq!(RankScale::Aga.rank(Rating(2000.0)).to_string(), "1d");
+    /// assert_eq!(RankScale::Kgs.rank(Rating(2000.0)).to_string(), "2d");
+    /// ```
+    pub fn rank(self, rating: Rating) -> Rank {
+        Rank(rating.0 + self.offset())
+    }
+
+    /// Rating in the middle of a rank on this scale.
+    /// ```
+    /// use gorating::RankScale;
+    /// let rank = "1d".parse().unwrap();
+    /// assert_eq!(RankScale::Aga.rating(rank).0, 2000.0);
+    /// ```
+    pub fn rating(self, rank: Rank) -> Rating {
+        Rating(rank.to_rating().0 - self.offset())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RankScale::Egd => "EGD",
+            RankScale::Aga => "AGA",
+            RankScale::Kgs => "KGS",
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Error parsing a rank scale from a string
+pub struct BadRankScale;
+
+impl std::str::FromStr for RankScale {
+    type Err = BadRankScale;
+
+    /// Parse the lowercase name of a scale, such as "aga"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "egd" => Ok(RankScale::Egd),
+            "aga" => Ok(RankScale::Aga),
+            "kgs" => Ok(RankScale::Kgs),
+            _ => Err(BadRankScale),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -229,6 +838,98 @@ mod test {
     fn test_handicap_from_str_errors() {
         assert!(Handicap::from_str("3w5").is_err());
         assert!(Handicap::from_str("3w8").is_err());
+        assert!(Handicap::from_str("2.25").is_err());
+        assert!(Handicap::from_str("H").is_err());
+        assert!(Handicap::from_str("H3 K0.3").is_err());
+        assert!(Handicap::from_str("H3 H4").is_err());
+        assert!(Handicap::from_str("H3 K250").is_err());
+        assert!(Handicap::from_str("reverse komi").is_err());
+        assert!(Handicap::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_handicap_from_str_structured() {
+        // Standard combinations use the compact encoding.
+        assert_eq!(Handicap::from_str("H0 K6.5").unwrap().0, 0.0);
+        assert_eq!(Handicap::from_str("K6½").unwrap().0, 0.0);
+        assert_eq!(Handicap::from_str("H1 K0").unwrap().0, 1.0);
+        assert_eq!(Handicap::from_str("H0 K-0.5").unwrap().0, 1.5);
+        assert_eq!(Handicap::from_str("h3").unwrap().0, 3.0);
+        assert_eq!(Handicap::from_str("H3 K-0.5").unwrap().0, 3.5);
+        for s in &[
+            "H3 K0.5",
+            "H0 K7.5",
+            "H9 K-3",
+            "reverse komi 5.5",
+            "H2 K-249.5",
+        ] {
+            let h = Handicap::from_str(s).unwrap();
+            assert!(h.0 < 0.0);
+            assert_eq!(h.to_string(), *s);
+        }
+        assert_eq!(
+            Handicap::from_str("H0 K-5.5").unwrap().to_string(),
+            "reverse komi 5.5"
+        );
+    }
+
+    #[test]
+    fn test_handicap_details_roundtrip() {
+        for stones in [0, 2, 3, 9, 25, MAX_STONES] {
+            for &komi in &[0.0, 0.5, 5.5, 6.5, 7.0, 100.0, MAX_KOMI] {
+                for &colour in &[Colour::Black, Colour::White] {
+                    let d = HandicapDetails::new(stones, komi, colour).unwrap();
+                    let h = Handicap::from(d);
+                    assert_eq!(h.details(), Some(d));
+                    assert_eq!(Handicap::from_str(&h.to_string()).unwrap().0, h.0);
+                }
+            }
+        }
+        assert!(Handicap::new(0.5).details().is_none());
+        assert!(Handicap::new(2.25).details().is_none());
+        assert_eq!(Handicap::new(0.5).to_string(), "0.5");
+    }
+
+    #[test]
+    fn test_handicap_effective() {
+        for &x in &[0.0, 1.0, 1.5, 2.0, 2.5, 9.0] {
+            assert_eq!(Handicap::new(x).effective(), x);
+        }
+        let h = |s| Handicap::from_str(s).unwrap().effective();
+        assert_eq!(h("H2 K0"), 2.0);
+        assert_eq!(h("H0 K19.5"), -1.5);
+        assert!(h("reverse komi 6.5") > 1.5 && h("reverse komi 6.5") < 2.0);
+        assert!(h("H0 K7.5") < 0.0);
+        assert!(h("H3 K0.5") > h("H2 K0.5"));
+    }
+
+    #[test]
+    fn test_handicap_effective_monotonic() {
+        let handicaps = [
+            "H0 K19.5",
+            "H0 K7.5",
+            "0w6½",
+            "H0 K3.5",
+            "0b0",
+            "0b5",
+            "reverse komi 5.5",
+            "H2 K6.5",
+            "2b0",
+            "2b5",
+            "H3 K6.5",
+            "H3 K0.5",
+            "3b0",
+            "3b5",
+            "H3 K-1",
+            "4b0",
+        ];
+        let values: Vec<f64> = handicaps
+            .iter()
+            .map(|s| Handicap::from_str(s).unwrap().effective())
+            .collect();
+        for (pair, names) in values.windows(2).zip(handicaps.windows(2)) {
+            assert!(pair[0] < pair[1], "{} < {}", names[0], names[1]);
+        }
     }
 
     #[test]
@@ -249,6 +950,7 @@ mod test {
     #[test]
     fn test_ratings_no_epsilon_1() {
         let sys = RatingSystem {
+            formula: Formula::Egd2021,
             bonus_factor: 0.0,
             min_rating: Rating(100.0),
             max_drop: 100.0,
@@ -263,6 +965,7 @@ mod test {
     #[test]
     fn test_ratings_no_epsilon_2() {
         let sys = RatingSystem {
+            formula: Formula::Egd2021,
             bonus_factor: 0.0,
             min_rating: Rating(100.0),
             max_drop: 100.0,
@@ -282,6 +985,7 @@ mod test {
     #[test]
     fn test_ratings_no_epsilon_handicap_5() {
         let sys = RatingSystem {
+            formula: Formula::Egd2021,
             bonus_factor: 0.0,
             min_rating: Rating(100.0),
             max_drop: 100.0,
@@ -302,6 +1006,7 @@ mod test {
     #[test]
     fn test_ratings_no_epsilon_generic_1() {
         let sys = RatingSystem {
+            formula: Formula::Egd2021,
             bonus_factor: 0.0,
             min_rating: Rating(-500.0),
             max_drop: 100.0,
@@ -326,6 +1031,7 @@ mod test {
     #[test]
     fn test_ratings_no_epsilon_generic_2() {
         let sys = RatingSystem {
+            formula: Formula::Egd2021,
             bonus_factor: 0.0,
             min_rating: Rating(-500.0),
             max_drop: 100.0,
@@ -355,6 +1061,7 @@ mod test {
     #[test]
     fn test_ratings_no_epsilon_generic_3() {
         let sys = RatingSystem {
+            formula: Formula::Egd2021,
             bonus_factor: 0.0,
             min_rating: Rating(-500.0),
             max_drop: 100.0,
@@ -409,6 +1116,129 @@ mod test {
         assert_eq!((adjb * 1000.0).round(), -22052.0);
     }
 
+    #[test]
+    fn test_con_legacy() {
+        let sys = RatingSystem::egd_legacy();
+        assert_eq!(sys.con(-200.0), 116.0);
+        assert_eq!(sys.con(100.0), 116.0);
+        assert_eq!(sys.con(1400.0), 51.0);
+        assert_eq!(sys.con(1450.0), 49.0);
+        assert_eq!(sys.con(2700.0), 10.0);
+        assert_eq!(sys.con(2900.0), 10.0);
+    }
+
+    /// Rating change in the legacy formula worked out from the constants of
+    /// the EGF rating system description as it stood before 2021, rather
+    /// than from the implementation: `con` as tabulated there, `a = 200 -
+    /// (GoR - 100) / 20` for the lower rating and `epsilon = 0.016`.
+    fn legacy_change(con: f64, a: f64, difference: f64, result: f64) -> f64 {
+        let expected = 1.0 / ((difference / a).exp() + 1.0) - 0.016 / 2.0;
+        con * (result - expected)
+    }
+
+    // Even game between two players rated 2000: con 27, a 105, expected
+    // result 0.492 for both.
+    #[test]
+    fn test_ratings_legacy_1() {
+        let sys = RatingSystem::egd_legacy();
+        let r = Rating(2000.0);
+        let adjw = sys.rating_adjustment(r, r, 0.0, 1.0);
+        assert!((adjw - legacy_change(27.0, 105.0, 0.0, 1.0)).abs() < 1e-9);
+        assert_eq!((adjw * 1000.0).round(), 13716.0);
+        let adjl = sys.rating_adjustment(r, r, 0.0, 0.0);
+        assert!((adjl - legacy_change(27.0, 105.0, 0.0, 0.0)).abs() < 1e-9);
+        assert_eq!((adjl * 1000.0).round(), -13284.0);
+    }
+
+    // Even game where the weaker player (1500, con 47) beats the stronger
+    // one (1700, con 39); a is 130 for both.
+    #[test]
+    fn test_ratings_legacy_2() {
+        let sys = RatingSystem::egd_legacy();
+        let rw = Rating(1700.0);
+        let rb = Rating(1500.0);
+        let adjw = sys.rating_adjustment(rw, rb, 0.0, 0.0);
+        assert!((adjw - legacy_change(39.0, 130.0, -200.0, 0.0)).abs() < 1e-9);
+        let adjb = sys.rating_adjustment(rb, rw, 0.0, 1.0);
+        assert!((adjb - legacy_change(47.0, 130.0, 200.0, 1.0)).abs() < 1e-9);
+    }
+
+    // Four stones between 1600 (con 43) and 1200 (con 60): black counts as
+    // 1550, so the difference is 50 and a is 127.5.
+    #[test]
+    fn test_ratings_legacy_handicap_4() {
+        let sys = RatingSystem::egd_legacy();
+        let rw = Rating(1600.0);
+        let rb = Rating(1200.0);
+        let adjw = sys.rating_adjustment(rw, rb, -4.0, 0.0);
+        assert!((adjw - legacy_change(43.0, 127.5, -50.0, 0.0)).abs() < 1e-9);
+        let adjb = sys.rating_adjustment(rb, rw, 4.0, 1.0);
+        assert!((adjb - legacy_change(60.0, 127.5, 50.0, 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_expected_result_symmetric() {
+        let sys = RatingSystem::new();
+        let rw = Rating(1800.0);
+        let rb = Rating(1450.0);
+        for &h in &[0.0, 1.0, 2.5, 4.0] {
+            let pw = sys.expected_result(rw, rb, -h);
+            let pb = sys.expected_result(rb, rw, h);
+            assert!((pw + pb - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_expected_result_handicap() {
+        let sys = RatingSystem::new();
+        let rw = Rating(1800.0);
+        let rb = Rating(1450.0);
+        let even = sys.expected_result(rb, rw, 0.0);
+        let handicap = sys.expected_result(rb, rw, 4.0);
+        assert!(even < 0.5);
+        assert!(handicap > even);
+        // 4 stones is worth 350 points, exactly the rating difference
+        assert!((handicap - sys.expected_result(rw, rw, 0.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_performance_rating() {
+        let sys = RatingSystem::new();
+        let r = Rating(1800.0);
+        // A jigo with a player of the same rating
+        let p = sys.performance_rating(&[(r, 0.0, 0.5)]).unwrap();
+        assert!((p.0 - 1800.0).abs() < 0.01);
+        // Winning half the games with 4 stones against a 350 points
+        // stronger player is performing at the expected level.
+        let games = [(r, 4.0, 1.0), (r, 4.0, 0.0)];
+        let p = sys.performance_rating(&games).unwrap();
+        assert!((p.0 - 1450.0).abs() < 0.01);
+        // So is the stronger player giving the handicap.
+        let games = [(Rating(1450.0), -4.0, 1.0), (Rating(1450.0), -4.0, 0.0)];
+        let p = sys.performance_rating(&games).unwrap();
+        assert!((p.0 - 1800.0).abs() < 0.01);
+        let games = [(r, 0.0, 1.0), (r, 0.0, 1.0), (r, 0.0, 0.0)];
+        let p = sys.performance_rating(&games).unwrap();
+        assert!(p.0 > 1800.0);
+        assert!((sys.expected_result(p, r, 0.0) * 3.0 - 2.0).abs() < 1e-4);
+        assert!(sys.performance_rating(&[]).is_none());
+        assert!(sys.performance_rating(&[(r, 0.0, 0.0)]).is_none());
+        let legacy = RatingSystem::egd_legacy();
+        let p = legacy.performance_rating(&games).unwrap();
+        assert!(p.0 > 1800.0);
+    }
+
+    #[test]
+    fn test_bonus() {
+        let sys = RatingSystem::new();
+        assert!(sys.bonus(Rating(100.0)) > 5.49);
+        assert!(sys.bonus(Rating(100.0)) < 5.51);
+        assert!(sys.bonus(Rating(2300.0)) > 0.13);
+        assert!(sys.bonus(Rating(2300.0)) < 0.14);
+        assert!(sys.bonus(Rating(2700.0)) < 0.002);
+        assert_eq!(RatingSystem::egd_legacy().bonus(Rating(100.0)), 0.0);
+    }
+
     fn adjust_rating(
         sys: &RatingSystem,
         r1: &mut Rating,
@@ -541,6 +1371,107 @@ mod test {
         assert_eq!(sys.calculate_handicap(324.0).to_string(), "3b5");
     }
 
+    #[test]
+    fn test_stone_ranks() {
+        assert_eq!(stone_ranks(19), 1.0);
+        assert!(stone_ranks(13) > 2.4);
+        assert!(stone_ranks(13) < 2.6);
+        assert!(stone_ranks(9) > 5.9);
+        assert!(stone_ranks(9) < 6.1);
+    }
+
+    #[test]
+    fn test_calculate_handicap_on_board() {
+        let sys = RatingSystem::new();
+        for &diff in &[0.0, 49.0, 51.0, 126.0, 324.0, 2000.0] {
+            assert_eq!(
+                sys.calculate_handicap_on_board(diff, 19).to_f64(),
+                sys.calculate_handicap(diff).to_f64()
+            );
+        }
+        assert_eq!(
+            sys.calculate_handicap_on_board(100.0, 13).to_string(),
+            "0w6½"
+        );
+        assert_eq!(
+            sys.calculate_handicap_on_board(250.0, 13).to_string(),
+            "0b5"
+        );
+        assert_eq!(
+            sys.calculate_handicap_on_board(500.0, 13).to_string(),
+            "2b5"
+        );
+        assert_eq!(
+            sys.calculate_handicap_on_board(250.0, 9).to_string(),
+            "0w6½"
+        );
+        assert_eq!(
+            sys.calculate_handicap_on_board(1200.0, 9).to_string(),
+            "2b5"
+        );
+    }
+
+    #[test]
+    fn test_calculate_handicap_with_policy() {
+        let sys = RatingSystem::new();
+        let h = |weaker, stronger, board_size, policy: &HandicapPolicy| {
+            sys.calculate_handicap_with_policy(Rating(weaker), Rating(stronger), board_size, policy)
+                .to_string()
+        };
+        let full = HandicapPolicy::full();
+        assert_eq!(h(1000.0, 1300.0, 19, &full), "3b5");
+        assert_eq!(h(500.0, 1800.0, 19, &full), "13b5");
+        assert_eq!(h(1000.0, 1500.0, 13, &full), "2b5");
+        let reduced = HandicapPolicy {
+            reduction: 1.0,
+            ..full
+        };
+        assert_eq!(h(1000.0, 1300.0, 19, &reduced), "2b5");
+        assert_eq!(h(1000.0, 1100.0, 19, &reduced), "0w6½");
+        assert_eq!(h(1000.0, 1500.0, 13, &reduced), "0b5");
+        let capped = HandicapPolicy {
+            max_stones: Some(9),
+            ..full
+        };
+        assert_eq!(h(500.0, 1800.0, 19, &capped), "9b0");
+        assert_eq!(h(1000.0, 1300.0, 19, &capped), "3b5");
+        let no_stones = HandicapPolicy {
+            max_stones: Some(1),
+            ..full
+        };
+        assert_eq!(h(500.0, 1800.0, 19, &no_stones), "0b0");
+        let strong_even = HandicapPolicy {
+            no_handicap_above: Some(Rating(2100.0)),
+            ..full
+        };
+        assert_eq!(h(2100.0, 2500.0, 19, &strong_even), "0w6½");
+        assert_eq!(h(2000.0, 2500.0, 19, &strong_even), "5b5");
+    }
+
+    #[test]
+    fn test_board_handicap() {
+        let sys = RatingSystem::new();
+        for &h in &[-3.5, -1.0, 0.0, 1.0, 1.5, 4.0] {
+            assert_eq!(sys.board_handicap(h, 19), h);
+            assert_eq!(sys.board_handicap(-h, 13), -sys.board_handicap(h, 13));
+        }
+        assert_eq!(sys.board_handicap(0.0, 9), 0.0);
+        // The recommended handicap on a small board is worth about the
+        // rating difference on 19×19.
+        let h = sys.calculate_handicap_on_board(1200.0, 9).to_f64();
+        let equivalent = sys.board_handicap(h, 9);
+        assert!((100.0 * (equivalent - 0.5) - 1200.0).abs() < 50.0);
+    }
+
+    #[test]
+    fn test_board_weight() {
+        let sys = RatingSystem::new();
+        assert_eq!(sys.board_weight(19), 1.0);
+        assert!(sys.board_weight(13) < 0.5);
+        assert!(sys.board_weight(9) < 0.25);
+        assert!(sys.board_weight(9) < sys.board_weight(13));
+    }
+
     #[test]
     fn test_rank_kyu() {
         assert_eq!(Rank(51.0).to_string(), "20k");
@@ -552,6 +1483,53 @@ mod test {
         assert_eq!(Rank(2049.0).to_string(), "1k");
     }
 
+    #[test]
+    fn test_rank_from_str() {
+        assert_eq!(Rank::from_str("20k").unwrap().0, 100.0);
+        assert_eq!(Rank::from_str("5k").unwrap().0, 1600.0);
+        assert_eq!(Rank::from_str("5 kyu").unwrap().0, 1600.0);
+        assert_eq!(Rank::from_str(" 5 Kyu ").unwrap().0, 1600.0);
+        assert_eq!(Rank::from_str("1k").unwrap().0, 2000.0);
+        assert_eq!(Rank::from_str("25k").unwrap().0, -400.0);
+        assert_eq!(Rank::from_str("1d").unwrap().0, 2100.0);
+        assert_eq!(Rank::from_str("2d").unwrap().0, 2200.0);
+        assert_eq!(Rank::from_str("2 dan").unwrap().0, 2200.0);
+        assert_eq!(Rank::from_str("2D").unwrap().0, 2200.0);
+    }
+
+    #[test]
+    fn test_rank_from_str_errors() {
+        assert!(Rank::from_str("").is_err());
+        assert!(Rank::from_str("5").is_err());
+        assert!(Rank::from_str("k").is_err());
+        assert!(Rank::from_str("0k").is_err());
+        assert!(Rank::from_str("31k").is_err());
+        assert!(Rank::from_str("0d").is_err());
+        assert!(Rank::from_str("10d").is_err());
+        assert!(Rank::from_str("5p").is_err());
+        assert!(Rank::from_str("-5k").is_err());
+        assert!(Rank::from_str("5 kyus").is_err());
+    }
+
+    #[test]
+    fn test_rank_round_trip() {
+        for s in &["30k", "20k", "10k", "1k", "1d", "7d", "9d"] {
+            assert_eq!(Rank::from_str(s).unwrap().to_string(), *s);
+            assert_eq!(
+                Rank::from_rating(Rank::from_str(s).unwrap().to_rating()).to_string(),
+                *s
+            );
+        }
+    }
+
+    #[test]
+    fn test_rank_to_rating() {
+        assert_eq!(Rank(51.0).to_rating().0, 100.0);
+        assert_eq!(Rank(149.0).to_rating().0, 100.0);
+        assert_eq!(Rank(2049.0).to_rating().0, 2000.0);
+        assert_eq!(Rank(2051.0).to_rating().0, 2100.0);
+    }
+
     #[test]
     fn test_rank_dan() {
         assert_eq!(Rank(2051.0).to_string(), "1d");
@@ -560,4 +1538,22 @@ mod test {
         assert_eq!(Rank(2249.0).to_string(), "2d");
         assert_eq!(Rank(2749.0).to_string(), "7d");
     }
+
+    #[test]
+    fn test_rank_scale() {
+        for &scale in &[RankScale::Egd, RankScale::Aga, RankScale::Kgs] {
+            for s in &["30k", "10k", "1k", "1d", "7d"] {
+                let rating = scale.rating(Rank::from_str(s).unwrap());
+                assert_eq!(scale.rank(rating).to_string(), *s);
+            }
+        }
+        assert_eq!(RankScale::Aga.rank(Rating(1600.0)).to_string(), "4k");
+        assert_eq!(RankScale::Kgs.rank(Rating(1600.0)).to_string(), "3k");
+        assert_eq!(
+            RankScale::Kgs.rating(Rank::from_str("1d").unwrap()).0,
+            1900.0
+        );
+        assert_eq!(RankScale::from_str("aga").unwrap(), RankScale::Aga);
+        assert!(RankScale::from_str("igs").is_err());
+    }
 }
@@ -0,0 +1,277 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! A [`Rating`] is serialized as a number. A [`Handicap`] or [`Rank`] is
+//! serialized in its human form, such as "3b5" or "5k", unless the field uses
+//! [`numeric`]. Both are accepted when deserializing, and invalid values are
+//! rejected.
+//! ```
+//! use gorating::{Handicap, Rank, Rating};
+//! let h: Handicap = serde_json::from_str("\"3b5\"").unwrap();
+//! assert_eq!(h.to_f64(), 3.5);
+//! let h: Handicap = serde_json::from_str("3.5").unwrap();
+//! assert_eq!(serde_json::to_string(&h).unwrap(), "\"3b5\"");
+//! let r: Rank = serde_json::from_str("\"5k\"").unwrap();
+//! assert_eq!(r.to_rating().0, 1600.0);
+//! assert!(serde_json::from_str::<Rank>("\"5x\"").is_err());
+//! assert_eq!(serde_json::to_string(&Rating(1234.5)).unwrap(), "1234.5");
+//! ```
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{Handicap, Rank, Rating};
+
+impl Serialize for Rating {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rating {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let x = f64::deserialize(deserializer)?;
+        if x.is_finite() {
+            Ok(Rating(x))
+        } else {
+            Err(de::Error::custom("rating is not a finite number"))
+        }
+    }
+}
+
+/// Conversion from and to the numeric form, implemented for [`Handicap`] and
+/// [`Rank`]
+pub trait Numeric: Sized + FromStr {
+    const EXPECTING: &'static str;
+
+    fn from_f64(x: f64) -> Option<Self>;
+
+    fn to_f64(&self) -> f64;
+
+    /// Whether the value can be serialized in both forms and read back,
+    /// checked after deserializing either form
+    fn is_valid(&self) -> bool;
+}
+
+impl Numeric for Handicap {
+    const EXPECTING: &'static str = "a handicap such as \"3b5\" or its numeric code";
+
+    fn from_f64(x: f64) -> Option<Self> {
+        Some(Handicap::new(x))
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.0
+    }
+
+    fn is_valid(&self) -> bool {
+        self.details().is_some()
+    }
+}
+
+impl Numeric for Rank {
+    const EXPECTING: &'static str = "a rank such as \"5k\" or a rating";
+
+    fn from_f64(x: f64) -> Option<Self> {
+        if x.is_finite() {
+            Some(Rank::from_rating(Rating(x)))
+        } else {
+            None
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.to_rating().0
+    }
+
+    /// Only ranks from 30k to 9d can be parsed again.
+    fn is_valid(&self) -> bool {
+        (-9.0..=29.0).contains(&(self.0 / 100.0).round())
+    }
+}
+
+struct NumericOrHuman<T>(std::marker::PhantomData<T>);
+
+impl<'de, T: Numeric> Visitor<'de> for NumericOrHuman<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(T::EXPECTING)
+    }
+
+    fn visit_f64<E: de::Error>(self, x: f64) -> Result<T, E> {
+        T::from_f64(x)
+            .filter(T::is_valid)
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Float(x), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, x: i64) -> Result<T, E> {
+        T::from_f64(x as f64)
+            .filter(T::is_valid)
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Signed(x), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, x: u64) -> Result<T, E> {
+        T::from_f64(x as f64)
+            .filter(T::is_valid)
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(x), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<T, E> {
+        T::from_str(s)
+            .ok()
+            .filter(T::is_valid)
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Str(s), &self))
+    }
+}
+
+fn deserialize_any<'de, T: Numeric, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    deserializer.deserialize_any(NumericOrHuman(std::marker::PhantomData))
+}
+
+impl Serialize for Handicap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Handicap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_any(deserializer)
+    }
+}
+
+impl Serialize for Rank {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rank {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_any(deserializer)
+    }
+}
+
+/// Serialize a [`Handicap`] as its numeric code or a [`Rank`] as the rating
+/// in the middle of the rank, for use with `#[serde(with = "...")]`.
+/// Deserializing accepts both forms.
+/// ```
+/// use gorating::Handicap;
+/// #[derive(serde::Serialize)]
+/// struct Game {
+///     #[serde(with = "gorating::serialization::numeric")]
+///     handicap: Handicap,
+/// }
+/// let game = Game { handicap: Handicap::new(3.5) };
+/// assert_eq!(serde_json::to_string(&game).unwrap(), "{\"handicap\":3.5}");
+/// ```
+pub mod numeric {
+    use super::*;
+
+    pub fn serialize<T: Numeric, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(value.to_f64())
+    }
+
+    pub fn deserialize<'de, T: Numeric, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        deserialize_any(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serde_rating() {
+        let r: Rating = serde_json::from_str("1000").unwrap();
+        assert_eq!(r.0, 1000.0);
+        assert!(serde_json::from_str::<Rating>("\"5k\"").is_err());
+    }
+
+    #[test]
+    fn test_serde_handicap() {
+        let h: Handicap = serde_json::from_str("\"H3 K0.5\"").unwrap();
+        assert_eq!(serde_json::to_string(&h).unwrap(), "\"H3 K0.5\"");
+        let h2: Handicap = serde_json::from_str(&h.to_f64().to_string()).unwrap();
+        assert_eq!(h2.to_f64(), h.to_f64());
+        let h: Handicap = serde_json::from_str("0").unwrap();
+        assert_eq!(serde_json::to_string(&h).unwrap(), "\"0w6½\"");
+        assert!(serde_json::from_str::<Handicap>("0.5").is_err());
+        assert!(serde_json::from_str::<Handicap>("\"3x\"").is_err());
+        assert!(serde_json::from_str::<Handicap>("true").is_err());
+    }
+
+    #[test]
+    fn test_serde_rank() {
+        let r: Rank = serde_json::from_str("\"2d\"").unwrap();
+        assert_eq!(serde_json::to_string(&r).unwrap(), "\"2d\"");
+        let r: Rank = serde_json::from_str("1130").unwrap();
+        assert_eq!(r.to_string(), "10k");
+        assert!(serde_json::from_str::<Rank>("\"31k\"").is_err());
+    }
+
+    #[test]
+    fn test_serde_rank_bounds() {
+        for (json, rank) in [
+            ("2949", "9d"),
+            ("\"9d\"", "9d"),
+            ("-949", "30k"),
+            ("\"30k\"", "30k"),
+        ] {
+            let r: Rank = serde_json::from_str(json).unwrap();
+            let human = serde_json::to_string(&r).unwrap();
+            assert_eq!(human, format!("\"{}\"", rank));
+            let r2: Rank = serde_json::from_str(&human).unwrap();
+            assert_eq!(r2.to_string(), rank);
+        }
+        for json in ["2950", "3500", "\"10d\"", "-950", "-1500", "\"31k\""] {
+            assert!(serde_json::from_str::<Rank>(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_serde_handicap_bounds() {
+        for json in ["0.5", "\"0.5\"", "-0.5", "\"-0.5\"", "1.25", "\"1.25\""] {
+            assert!(serde_json::from_str::<Handicap>(json).is_err(), "{}", json);
+        }
+        for json in [
+            "0",
+            "1",
+            "1.5",
+            "9",
+            "9.5",
+            "\"1.5\"",
+            "\"2\"",
+            "\"reverse komi 5.5\"",
+        ] {
+            let h: Handicap = serde_json::from_str(json).unwrap();
+            let human = serde_json::to_string(&h).unwrap();
+            let h2: Handicap = serde_json::from_str(&human).unwrap();
+            assert_eq!(h2.to_f64(), h.to_f64(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_serde_numeric() {
+        #[derive(Serialize, Deserialize)]
+        struct Numbers {
+            #[serde(with = "numeric")]
+            handicap: Handicap,
+            #[serde(with = "numeric")]
+            rank: Rank,
+        }
+        let n: Numbers = serde_json::from_str("{\"handicap\": \"2b0\", \"rank\": \"5k\"}").unwrap();
+        assert_eq!(
+            serde_json::to_string(&n).unwrap(),
+            "{\"handicap\":2.0,\"rank\":1600.0}"
+        );
+        assert!(serde_json::from_str::<Numbers>("{\"handicap\": -1, \"rank\": 1}").is_err());
+    }
+}
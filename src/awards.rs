@@ -0,0 +1,273 @@
+//! Awards for the season, computed from the games and ratings
+use std::collections::{HashMap, HashSet};
+
+use actix_web::Responder;
+use askama::Template;
+
+use crate::db::load_settings;
+use crate::models::Percentage;
+use crate::update_ratings::{rated_games, RatedGame};
+use crate::{CommonTemplate, Result};
+
+/// A player who receives an award, with what it was awarded for
+#[derive(Debug)]
+pub struct Winner {
+    pub id: i32,
+    pub name: String,
+    pub detail: String,
+}
+
+/// An award with all players who tied for it
+#[derive(Debug)]
+pub struct Award {
+    pub title: &'static str,
+    pub winners: Vec<Winner>,
+}
+
+#[derive(Template)]
+#[template(path = "awards.html")]
+struct AwardsTemplate {
+    min_games: u32,
+    awards: Vec<Award>,
+}
+impl CommonTemplate for AwardsTemplate {}
+
+pub(crate) fn awards(conn: &rusqlite::Connection) -> Result<impl Responder> {
+    awards_internal(conn)
+}
+
+/// The candidates with the highest key, as `(key, id, detail)`
+fn top(candidates: Vec<(f64, i32, String)>, names: &HashMap<i32, String>) -> Vec<Winner> {
+    let best = candidates
+        .iter()
+        .map(|&(key, _, _)| key)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let mut winners: Vec<Winner> = candidates
+        .into_iter()
+        .filter(|&(key, _, _)| key >= best - 1e-9)
+        .map(|(_, id, detail)| Winner {
+            id,
+            name: names[&id].clone(),
+            detail,
+        })
+        .collect();
+    winners.sort_by(|a, b| a.name.cmp(&b.name));
+    winners
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+fn awards_internal(conn: &rusqlite::Connection) -> Result<AwardsTemplate> {
+    let settings = load_settings(conn)?;
+    let min_games = settings.award_min_games;
+    let mut stmt = conn.prepare("SELECT id, name, initialrating, currentrating FROM players")?;
+    let players: Vec<(i32, String, f64, f64)> = stmt
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let names: HashMap<i32, String> = players
+        .iter()
+        .map(|(id, name, _, _)| (*id, name.clone()))
+        .collect();
+    let mut stmt = conn.prepare(
+        "SELECT g.id, CAST(r.date AS TEXT) FROM games g, rounds r WHERE g.played = r.id",
+    )?;
+    let dates: HashMap<i32, String> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let games: Vec<RatedGame> = rated_games(conn, &settings)?;
+
+    let mut played: HashMap<i32, usize> = HashMap::new();
+    let mut opponents: HashMap<i32, HashSet<i32>> = HashMap::new();
+    let mut streaks: HashMap<i32, (usize, usize)> = HashMap::new();
+    // The least expected win of every player: expected result, opponent and
+    // game
+    let mut upsets: HashMap<i32, (f64, i32, i32)> = HashMap::new();
+    for rg in &games {
+        let game = &rg.game;
+        let sides = [
+            (
+                game.black,
+                game.white,
+                1.0 - game.white_result,
+                rg.black_expected,
+            ),
+            (game.white, game.black, game.white_result, rg.white_expected),
+        ];
+        for &(player, opponent, result, expected) in &sides {
+            *played.entry(player).or_default() += 1;
+            opponents.entry(player).or_default().insert(opponent);
+            let (current, longest) = streaks.entry(player).or_default();
+            if result == 1.0 {
+                *current += 1;
+                *longest = usize::max(*longest, *current);
+                let upset = upsets.entry(player).or_insert((expected, opponent, rg.id));
+                if expected < upset.0 {
+                    *upset = (expected, opponent, rg.id);
+                }
+            } else {
+                *current = 0;
+            }
+        }
+    }
+    let games_of = |id: &i32| played.get(id).copied().unwrap_or(0);
+    let eligible = |id: &i32| games_of(id) >= min_games as usize && games_of(id) > 0;
+
+    let improvement = players
+        .iter()
+        .filter(|(id, _, _, _)| eligible(id))
+        .map(|&(id, _, initial, current)| {
+            let diff = current - initial;
+            (diff, id, format!("{:+.0} rating points", diff))
+        })
+        .collect();
+    let most_games = played
+        .iter()
+        .filter(|(id, _)| eligible(id))
+        .map(|(&id, &n)| (n as f64, id, plural(n, "game", "games")))
+        .collect();
+    let streak = streaks
+        .iter()
+        .filter(|(id, &(_, longest))| eligible(id) && longest > 0)
+        .map(|(&id, &(_, longest))| (longest as f64, id, plural(longest, "win", "wins")))
+        .collect();
+    let upset = upsets
+        .iter()
+        .filter(|(id, &(expected, _, _))| eligible(id) && expected < 0.5)
+        .map(|(&id, &(expected, opponent, game))| {
+            let detail = format!(
+                "won against {} on {} with a win chance of {}",
+                names[&opponent],
+                dates[&game],
+                Percentage(expected)
+            );
+            (-expected, id, detail)
+        })
+        .collect();
+    let distinct = opponents
+        .iter()
+        .filter(|(id, _)| eligible(id))
+        .map(|(&id, set)| {
+            (
+                set.len() as f64,
+                id,
+                plural(set.len(), "opponent", "opponents"),
+            )
+        })
+        .collect();
+    let awards = vec![
+        Award {
+            title: "Most improved rating",
+            winners: top(improvement, &names),
+        },
+        Award {
+            title: "Most games played",
+            winners: top(most_games, &names),
+        },
+        Award {
+            title: "Longest winning streak",
+            winners: top(streak, &names),
+        },
+        Award {
+            title: "Best result against a stronger opponent",
+            winners: top(upset, &names),
+        },
+        Award {
+            title: "Most distinct opponents",
+            winners: top(distinct, &names),
+        },
+    ];
+    Ok(AwardsTemplate { min_games, awards })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ensure_schema, save_settings};
+    use crate::update_ratings::update_ratings;
+
+    fn setup() -> rusqlite::Connection {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1200.0, 1200.0), ",
+            "(43, \"player3\", 1000.0, 1000.0), ",
+            "(44, \"player4\", 1000.0, 1000.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES ",
+            "(97, '2019-06-03'), (98, '2019-06-10'), (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(31, 97, 42, 41, 'BlackWins'), ",
+            "(32, 97, 43, 44, 'WhiteWins'), ",
+            "(33, 98, 43, 41, 'BlackWins'), ",
+            "(34, 98, 42, 44, 'WhiteWins'), ",
+            "(35, 99, 44, 41, 'WhiteWins'), ",
+            "(36, 99, 42, 43, 'WhiteWinsByDefault');"
+        ))
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        conn
+    }
+
+    fn winners(at: &AwardsTemplate, title: &str) -> Vec<(i32, String)> {
+        let award = at.awards.iter().find(|a| a.title == title).unwrap();
+        award
+            .winners
+            .iter()
+            .map(|w| (w.id, w.detail.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn awards_1() {
+        let conn = setup();
+        let mut settings = load_settings(&conn).unwrap();
+        settings.award_min_games = 3;
+        save_settings(&conn, &settings).unwrap();
+        let at = awards_internal(&conn).unwrap();
+        assert_eq!(at.min_games, 3);
+        // Players 2 and 3 played only two rated games, as the forfeit does
+        // not count.
+        let improved = winners(&at, "Most improved rating");
+        assert_eq!(improved.len(), 1);
+        assert_eq!(improved[0].0, 41);
+        assert!(improved[0].1.starts_with('+'));
+        assert_eq!(
+            winners(&at, "Most games played"),
+            [(41, "3 games".to_owned()), (44, "3 games".to_owned())]
+        );
+        assert_eq!(
+            winners(&at, "Longest winning streak"),
+            [(41, "2 wins".to_owned())]
+        );
+        let upset = winners(&at, "Best result against a stronger opponent");
+        assert_eq!(upset.len(), 1);
+        assert_eq!(upset[0].0, 41);
+        assert!(upset[0]
+            .1
+            .starts_with("won against player2 on 2019-06-03 with a win chance of "));
+        assert_eq!(
+            winners(&at, "Most distinct opponents"),
+            [
+                (41, "3 opponents".to_owned()),
+                (44, "3 opponents".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn awards_min_games() {
+        let conn = setup();
+        let at = awards_internal(&conn).unwrap();
+        // Nobody played the default minimum of 5 games.
+        for award in &at.awards {
+            assert!(award.winners.is_empty(), "{}", award.title);
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Rating what-if calculator page
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use actix_web::Responder;
+use askama::Template;
+
+use gorating::{Handicap, RankScale, Rating, RatingSystem};
+
+use crate::db::load_settings;
+use crate::models::{Colour, Player, Settings, WhatIf, WhatIfPlayer, WhatIfResult};
+use crate::update_ratings::provisional_players;
+use crate::{parse_boardsize, parse_initial_rating, CommonTemplate, Error, Result};
+
+#[derive(Template)]
+#[template(path = "calculator.html")]
+struct CalculatorTemplate {
+    players: Vec<Player>,
+    black: i32,
+    white: i32,
+    black_rating: String,
+    white_rating: String,
+    handicap: String,
+    boardsize: u32,
+    max_drop: Option<f64>,
+    max_drop_period: &'static str,
+    min_rating: Rating,
+    rank_scale: RankScale,
+    what_if: Option<WhatIf>,
+}
+impl CommonTemplate for CalculatorTemplate {}
+
+pub(crate) fn calculator(
+    conn: &rusqlite::Connection,
+    params: &HashMap<String, String>,
+) -> Result<impl Responder> {
+    calculator_internal(conn, params)
+}
+
+/// One side of the game: name, rating and whether the rating is provisional
+type Side = (String, Rating, bool);
+
+/// A player selected from the list or a rating entered directly. An entered
+/// rating is that of a new player, so it is provisional if the settings have
+/// provisional ratings at all.
+fn parse_side(
+    players: &[Player],
+    provisional: &HashSet<i32>,
+    params: &HashMap<String, String>,
+    colour: Colour,
+    settings: &Settings,
+) -> Result<Option<Side>> {
+    let id = params.get(colour.name()).map(String::as_str).unwrap_or("");
+    if !id.is_empty() {
+        let id = i32::from_str(id).map_err(|_| Error::BadParam("player"))?;
+        let player = players
+            .iter()
+            .find(|p| p.id == id)
+            .ok_or(Error::BadParam("player not found"))?;
+        return Ok(Some((
+            player.name.clone(),
+            player.rating,
+            provisional.contains(&id),
+        )));
+    }
+    match params.get(&format!("{}_rating", colour.name())) {
+        Some(s) if !s.trim().is_empty() => Ok(Some((
+            s.trim().to_owned(),
+            Rating(parse_initial_rating(s, settings)?),
+            settings.provisional_games > 0,
+        ))),
+        _ => Ok(None),
+    }
+}
+
+/// The possible rating changes of one player. `factor` is applied to the
+/// adjustment before the maximum drop, like the board weight and provisional
+/// factor are when the ratings are updated.
+fn what_if_player(
+    sys: &RatingSystem,
+    (name, rating, provisional): Side,
+    colour: Colour,
+    other_rating: Rating,
+    handicap: f64,
+    factor: f64,
+) -> WhatIfPlayer {
+    let results = [("Win", 1.0), ("Jigo", 0.5), ("Loss", 0.0)]
+        .iter()
+        .map(|&(result, score)| {
+            let adjustment = factor * sys.rating_adjustment(rating, other_rating, handicap, score);
+            let limited_adjustment = f64::max(adjustment, -sys.max_drop);
+            WhatIfResult {
+                result,
+                adjustment,
+                limited_adjustment,
+                new_rating: sys.adjust_rating(rating, limited_adjustment),
+            }
+        })
+        .collect();
+    WhatIfPlayer {
+        name,
+        colour,
+        rating,
+        provisional,
+        expected: sys.expected_result(rating, other_rating, handicap),
+        bonus: sys.bonus(rating),
+        results,
+    }
+}
+
+fn calculator_internal(
+    conn: &rusqlite::Connection,
+    params: &HashMap<String, String>,
+) -> Result<CalculatorTemplate> {
+    let mut stmt = conn
+        .prepare("SELECT id, name, currentrating FROM players ORDER BY currentrating DESC, id")?;
+    let players: Vec<Player> = stmt
+        .query_map([], |row| {
+            let id: i32 = row.get(0)?;
+            let name: String = row.get(1)?;
+            let rating = Rating::new(row.get(2)?);
+            Ok(Player { id, name, rating })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let settings = load_settings(conn)?;
+    let sys = settings.rating_system();
+    let provisional = provisional_players(conn, &settings)?;
+    let black = parse_side(&players, &provisional, params, Colour::Black, &settings)?;
+    let white = parse_side(&players, &provisional, params, Colour::White, &settings)?;
+    let handicap = params.get("handicap").cloned().unwrap_or_default();
+    let boardsize = parse_boardsize(params, "boardsize")?;
+    let what_if = match (black, white) {
+        (Some(black), Some(white)) => {
+            let h = if handicap.trim().is_empty() {
+                sys.calculate_handicap_with_policy(
+                    black.1,
+                    Rating(f64::max(white.1 .0, black.1 .0)),
+                    boardsize,
+                    &settings.handicap_policy(),
+                )
+            } else {
+                Handicap::from_str(handicap.trim()).map_err(|_| Error::BadParam("handicap"))?
+            };
+            let board_handicap = sys.board_handicap(h.effective(), boardsize);
+            let weight = sys.board_weight(boardsize);
+            let factor = |provisional| {
+                if provisional {
+                    weight * settings.provisional_factor
+                } else {
+                    weight
+                }
+            };
+            let (black_factor, white_factor) = (factor(black.2), factor(white.2));
+            let (black_rating, white_rating) = (black.1, white.1);
+            Some(WhatIf {
+                handicap: h,
+                players: [
+                    what_if_player(
+                        &sys,
+                        black,
+                        Colour::Black,
+                        white_rating,
+                        board_handicap,
+                        black_factor,
+                    ),
+                    what_if_player(
+                        &sys,
+                        white,
+                        Colour::White,
+                        black_rating,
+                        -board_handicap,
+                        white_factor,
+                    ),
+                ],
+            })
+        }
+        _ => None,
+    };
+    let get_param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    Ok(CalculatorTemplate {
+        players,
+        black: i32::from_str(&get_param("black")).unwrap_or(0),
+        white: i32::from_str(&get_param("white")).unwrap_or(0),
+        black_rating: get_param("black_rating"),
+        white_rating: get_param("white_rating"),
+        handicap,
+        boardsize,
+        max_drop: settings.max_drop,
+        max_drop_period: settings.max_drop_period.name(),
+        min_rating: sys.min_rating,
+        rank_scale: settings.rank_scale,
+        what_if,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    fn params(list: &[(&str, &str)]) -> HashMap<String, String> {
+        list.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn calculator_empty() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let ct = calculator_internal(&conn, &HashMap::new()).unwrap();
+        assert!(ct.players.is_empty());
+        assert!(ct.what_if.is_none());
+    }
+
+    #[test]
+    fn calculator_ratings() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let ct = calculator_internal(
+            &conn,
+            &params(&[
+                ("black", ""),
+                ("black_rating", "1000"),
+                ("white", ""),
+                ("white_rating", "5k"),
+                ("handicap", ""),
+            ]),
+        )
+        .unwrap();
+        let what_if = ct.what_if.unwrap();
+        assert_eq!(what_if.handicap.to_string(), "6b5");
+        assert_eq!(what_if.black().rating.0, 1000.0);
+        assert_eq!(what_if.white().rating.0, 1600.0);
+        assert!((what_if.black().expected + what_if.white().expected - 1.0).abs() < 1e-9);
+        let results: Vec<_> = what_if.black().results.iter().map(|r| r.result).collect();
+        assert_eq!(results, ["Win", "Jigo", "Loss"]);
+        for r in what_if
+            .black()
+            .results
+            .iter()
+            .chain(&what_if.white().results)
+        {
+            assert!(r.limited_adjustment >= -ct.max_drop.unwrap());
+        }
+    }
+
+    #[test]
+    fn calculator_players() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        {
+            let trans = conn.transaction().unwrap();
+            trans
+                .execute(
+                    concat!(
+                        "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+                        "(41, \"player1\", 1000.0, 1020.0), ",
+                        "(42, \"player2\", 1000.0, 980.0);"
+                    ),
+                    [],
+                )
+                .unwrap();
+            trans.commit().unwrap();
+        }
+        let ct = calculator_internal(
+            &conn,
+            &params(&[("black", "42"), ("white", "41"), ("handicap", "0b0")]),
+        )
+        .unwrap();
+        assert_eq!(ct.black, 42);
+        assert_eq!(ct.white, 41);
+        let what_if = ct.what_if.unwrap();
+        assert_eq!(what_if.handicap.to_f64(), 1.0);
+        assert_eq!(what_if.black().name, "player2");
+        assert_eq!(what_if.black().rating.0, 980.0);
+        assert_eq!(what_if.white().name, "player1");
+        assert!(what_if.black().expected > 0.5);
+    }
+
+    #[test]
+    fn calculator_handicap_policy() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let mut settings = load_settings(&conn).unwrap();
+        settings.handicap_reduction = 1.0;
+        settings.max_handicap = Some(4);
+        crate::db::save_settings(&conn, &settings).unwrap();
+        let handicap = |black, white| {
+            calculator_internal(
+                &conn,
+                &params(&[("black_rating", black), ("white_rating", white)]),
+            )
+            .unwrap()
+            .what_if
+            .unwrap()
+            .handicap
+            .to_string()
+        };
+        assert_eq!(handicap("1000", "1300"), "2b5");
+        assert_eq!(handicap("1000", "2000"), "4b0");
+        assert_eq!(handicap("1300", "1000"), "0w6½");
+    }
+
+    #[test]
+    fn calculator_structured_handicap() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let what_if = |handicap| {
+            calculator_internal(
+                &conn,
+                &params(&[
+                    ("black_rating", "1500"),
+                    ("white_rating", "1500"),
+                    ("handicap", handicap),
+                ]),
+            )
+            .unwrap()
+            .what_if
+            .unwrap()
+        };
+        let even = what_if("0w6½");
+        let reverse = what_if("reverse komi 5.5");
+        assert_eq!(reverse.handicap.to_string(), "reverse komi 5.5");
+        assert!(reverse.black().expected > even.black().expected);
+        let large_komi = what_if("H0 K13.5");
+        assert!(large_komi.black().expected < even.black().expected);
+    }
+
+    #[test]
+    fn calculator_provisional_small_board() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let mut settings = load_settings(&conn).unwrap();
+        settings.provisional_games = 1;
+        crate::db::save_settings(&conn, &settings).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1100.0, 1100.0), ",
+            "(43, \"player3\", 1100.0, 1100.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (98, '2019-06-10'), (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, handicap, result) VALUES ",
+            "(31, 98, 42, 43, 0.0, 'WhiteWins');"
+        ))
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        crate::update_ratings::update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let what_if = calculator_internal(
+            &conn,
+            &params(&[
+                ("black", "41"),
+                ("white", "42"),
+                ("handicap", "0b0"),
+                ("boardsize", "13"),
+            ]),
+        )
+        .unwrap()
+        .what_if
+        .unwrap();
+        assert!(what_if.black().provisional);
+        assert!(!what_if.white().provisional);
+
+        // Play the game and compare with the ratings that are stored.
+        conn.execute(
+            concat!(
+                "INSERT INTO games (id, played, white, black, handicap, boardsize, result) ",
+                "VALUES (32, 99, 42, 41, 1.0, 13, 'BlackWins')"
+            ),
+            [],
+        )
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        crate::update_ratings::update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let rating = |id: i32| -> f64 {
+            conn.query_row(
+                "SELECT currentrating FROM players WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let win = &what_if.black().results[0];
+        assert_eq!(win.result, "Win");
+        assert!((win.new_rating.0 - rating(41)).abs() < 1e-9);
+        let loss = &what_if.white().results[2];
+        assert_eq!(loss.result, "Loss");
+        assert!((loss.new_rating.0 - rating(42)).abs() < 1e-9);
+        // Both the provisional factor and the board weight are included.
+        let sys = RatingSystem::new();
+        let handicap = sys.board_handicap(1.0, 13);
+        let unweighted =
+            sys.rating_adjustment(Rating(1000.0), what_if.white().rating, handicap, 1.0);
+        let expected = 2.0 * sys.board_weight(13) * unweighted;
+        assert!((win.adjustment - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn what_if_max_drop() {
+        let sys = RatingSystem {
+            max_drop: 10.0,
+            ..RatingSystem::new()
+        };
+        let wip = what_if_player(
+            &sys,
+            ("player".to_owned(), Rating(1500.0), false),
+            Colour::White,
+            Rating(1500.0),
+            0.0,
+            1.0,
+        );
+        let loss = &wip.results[2];
+        assert_eq!(loss.result, "Loss");
+        assert!(loss.adjustment < -10.0);
+        assert_eq!(loss.limited_adjustment, -10.0);
+        assert_eq!(loss.new_rating.0, 1490.0);
+        let win = &wip.results[0];
+        assert_eq!(win.adjustment, win.limited_adjustment);
+    }
+
+    #[test]
+    fn calculator_errors() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        assert!(calculator_internal(&conn, &params(&[("black", "7")])).is_err());
+        assert!(calculator_internal(&conn, &params(&[("white_rating", "strong")])).is_err());
+        assert!(calculator_internal(
+            &conn,
+            &params(&[
+                ("black_rating", "1000"),
+                ("white_rating", "1000"),
+                ("handicap", "3w8")
+            ])
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Crosstable with the results between every pair of players
+use std::collections::HashMap;
+
+use actix_web::Responder;
+use askama::Template;
+
+use gorating::{Handicap, Rating};
+
+use crate::models::{FormattableGameResult, GameResult, Links, OneSidedGameResult, Player};
+use crate::{CommonTemplate, Result};
+
+/// Results of a player against one opponent in played games
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub wins: u32,
+    pub losses: u32,
+    pub jigos: u32,
+}
+
+impl Record {
+    /// Count a result, leaving out forfeits
+    pub(crate) fn add(&mut self, result: OneSidedGameResult) {
+        match result {
+            OneSidedGameResult::Win => self.wins += 1,
+            OneSidedGameResult::Lose => self.losses += 1,
+            OneSidedGameResult::Jigo => self.jigos += 1,
+            OneSidedGameResult::WinByDefault | OneSidedGameResult::LoseByDefault => (),
+        }
+    }
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}–{}–{}", self.wins, self.losses, self.jigos)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "crosstable.html")]
+struct CrosstableTemplate {
+    players: Vec<Player>,
+    /// Record of the first player against the second, for every pair that
+    /// played at least one game including forfeits
+    records: HashMap<(i32, i32), Record>,
+    links: Links,
+}
+impl CommonTemplate for CrosstableTemplate {
+    fn links(&self) -> Links {
+        self.links
+    }
+}
+
+impl CrosstableTemplate {
+    fn record(&self, player: &i32, opponent: &i32) -> Option<Record> {
+        self.records.get(&(*player, *opponent)).copied()
+    }
+}
+
+pub(crate) fn crosstable(conn: &rusqlite::Connection) -> Result<impl Responder> {
+    crosstable_internal(conn)
+}
+
+fn crosstable_internal(conn: &rusqlite::Connection) -> Result<CrosstableTemplate> {
+    let mut stmt = conn
+        .prepare("SELECT id, name, currentrating FROM players ORDER BY currentrating DESC, id")?;
+    let players: Vec<Player> = stmt
+        .query_map([], |row| {
+            let id: i32 = row.get(0)?;
+            let name: String = row.get(1)?;
+            let rating = Rating::new(row.get(2)?);
+            Ok(Player { id, name, rating })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut records: HashMap<(i32, i32), Record> = HashMap::new();
+    let mut stmt =
+        conn.prepare("SELECT white, black, result FROM games WHERE result IS NOT NULL")?;
+    for row in stmt.query_map([], |row| {
+        let white: i32 = row.get(0)?;
+        let black: i32 = row.get(1)?;
+        let result: GameResult = row.get(2)?;
+        Ok((white, black, result))
+    })? {
+        let (white, black, result) = row?;
+        let sides = [
+            (white, black, result.seen_from_white()),
+            (black, white, result.seen_from_black()),
+        ];
+        for &(player, opponent, result) in &sides {
+            records.entry((player, opponent)).or_default().add(result);
+        }
+    }
+    Ok(CrosstableTemplate {
+        players,
+        records,
+        links: Links::Live,
+    })
+}
+
+/// Render the crosstable for the static website
+pub(crate) fn render_static(conn: &rusqlite::Connection) -> Result<String> {
+    let mut ct = crosstable_internal(conn)?;
+    ct.links = Links::Static { root: "" };
+    Ok(ct.render()?)
+}
+
+/// A game between the two players of a pair
+#[derive(Debug)]
+pub struct PairGame {
+    pub round_id: i32,
+    pub date: String,
+    pub black: String,
+    pub white: String,
+    pub handicap: Handicap,
+    pub boardsize: u32,
+    pub result: FormattableGameResult,
+}
+
+#[derive(Template)]
+#[template(path = "crosstable_pair.html")]
+struct PairTemplate {
+    player: Player,
+    opponent: Player,
+    record: Record,
+    games: Vec<PairGame>,
+}
+impl CommonTemplate for PairTemplate {}
+
+pub(crate) fn pair(
+    conn: &rusqlite::Connection,
+    player_id: i32,
+    opponent_id: i32,
+) -> Result<impl Responder> {
+    pair_internal(conn, player_id, opponent_id)
+}
+
+fn load_player(conn: &rusqlite::Connection, id: i32) -> rusqlite::Result<Player> {
+    conn.query_row(
+        "SELECT id, name, currentrating FROM players WHERE id = ?1",
+        [id],
+        |row| {
+            Ok(Player {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                rating: Rating::new(row.get(2)?),
+            })
+        },
+    )
+}
+
+fn pair_internal(
+    conn: &rusqlite::Connection,
+    player_id: i32,
+    opponent_id: i32,
+) -> Result<PairTemplate> {
+    let player = load_player(conn, player_id)?;
+    let opponent = load_player(conn, opponent_id)?;
+    let mut stmt = conn.prepare(concat!(
+        "SELECT r.id, CAST(r.date AS TEXT), g.black, g.handicap, g.boardsize, g.result ",
+        "FROM games g, rounds r ",
+        "WHERE g.played = r.id AND ((g.white = ?1 AND g.black = ?2) OR (g.white = ?2 AND g.black = ?1)) ",
+        "ORDER BY r.date, g.id"
+    ))?;
+    let mut record = Record::default();
+    let games = stmt
+        .query_map([player_id, opponent_id], |row| {
+            let black: i32 = row.get(2)?;
+            let result: Option<GameResult> = row.get(5)?;
+            let (black, white) = if black == player_id {
+                (&player, &opponent)
+            } else {
+                (&opponent, &player)
+            };
+            if let Some(result) = result {
+                record.add(if black.id == player_id {
+                    result.seen_from_black()
+                } else {
+                    result.seen_from_white()
+                });
+            }
+            Ok(PairGame {
+                round_id: row.get(0)?,
+                date: row.get(1)?,
+                black: black.name.clone(),
+                white: white.name.clone(),
+                handicap: Handicap::new(row.get(3)?),
+                boardsize: row.get(4)?,
+                result: FormattableGameResult(result),
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(PairTemplate {
+        player,
+        opponent,
+        record,
+        games,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    fn setup() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1020.0), ",
+            "(42, \"player2\", 1000.0, 980.0), ",
+            "(43, \"player3\", 1000.0, 1000.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES ",
+            "(98, '2019-06-10'), (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, handicap, result) VALUES ",
+            "(31, 98, 41, 42, 0.0, 'WhiteWins'), ",
+            "(32, 98, 43, 41, 0.0, 'Jigo'), ",
+            "(33, 99, 42, 41, 0.0, 'WhiteWins'), ",
+            "(34, 99, 41, 42, 0.0, 'BlackWinsByDefault'), ",
+            "(35, 99, 43, 42, 2.0, NULL);"
+        ))
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn crosstable_1() {
+        let conn = setup();
+        let ct = crosstable_internal(&conn).unwrap();
+        let ids: Vec<_> = ct.players.iter().map(|p| p.id).collect();
+        assert_eq!(ids, [41, 43, 42]);
+        let record = |a, b| ct.record(&a, &b);
+        assert_eq!(
+            record(41, 42),
+            Some(Record {
+                wins: 1,
+                losses: 1,
+                jigos: 0
+            })
+        );
+        assert_eq!(
+            record(42, 41),
+            Some(Record {
+                wins: 1,
+                losses: 1,
+                jigos: 0
+            })
+        );
+        assert_eq!(record(43, 41).unwrap().to_string(), "0–0–1");
+        // The game without a result does not count yet.
+        assert_eq!(record(43, 42), None);
+        assert_eq!(record(41, 41), None);
+    }
+
+    #[test]
+    fn pair_1() {
+        let conn = setup();
+        let pt = pair_internal(&conn, 42, 41).unwrap();
+        assert_eq!(pt.player.name, "player2");
+        assert_eq!(pt.opponent.name, "player1");
+        assert_eq!(pt.record.to_string(), "1–1–0");
+        assert_eq!(pt.games.len(), 3);
+        let g = &pt.games[0];
+        assert_eq!((g.round_id, g.date.as_str()), (98, "2019-06-10"));
+        assert_eq!((g.black.as_str(), g.white.as_str()), ("player2", "player1"));
+        assert_eq!(g.result.to_string(), "0-1");
+        assert_eq!(pt.games[2].result.to_string(), "1-0!");
+        let pt = pair_internal(&conn, 43, 42).unwrap();
+        assert_eq!(pt.games.len(), 1);
+        assert_eq!(pt.games[0].result.to_string(), "?-?");
+        assert!(pair_internal(&conn, 41, 49).is_err());
+    }
+}
@@ -3,12 +3,14 @@ use askama::Template;
 use rusqlite::params;
 use serde::{Deserialize, Serialize};
 
+use gorating::Rating;
+
 use crate::{CommonTemplate, Result};
 
 #[derive(Deserialize, Serialize, Debug)]
 struct Player {
     name: String,
-    rating: f64,
+    rating: Rating,
     default_schedule: bool,
 }
 
@@ -44,7 +46,7 @@ fn export_internal(conn: &rusqlite::Connection) -> Result<String> {
         .query_map([], |row| {
             Ok(Player {
                 name: row.get(0)?,
-                rating: row.get(1)?,
+                rating: Rating::new(row.get(1)?),
                 default_schedule: row.get(2)?,
             })
         })?
@@ -103,7 +105,7 @@ fn import_internal(conn: &mut rusqlite::Connection, json: &str) -> Result<Import
             if qstmt.exists(&[&p.name])? {
                 result.skipped += 1;
             } else {
-                istmt.execute(params![&p.name, &p.rating, &p.default_schedule])?;
+                istmt.execute(params![&p.name, &p.rating.0, &p.default_schedule])?;
                 result.imported += 1;
             }
         }
@@ -151,11 +153,11 @@ mod tests {
         assert_eq!(data.players.len(), 2);
         let p1 = &data.players[0];
         assert_eq!(p1.name, "player1");
-        assert_eq!(p1.rating, 1020.0);
+        assert_eq!(p1.rating.0, 1020.0);
         assert_eq!(p1.default_schedule, false);
         let p2 = &data.players[1];
         assert_eq!(p2.name, "player2");
-        assert_eq!(p2.rating, 980.0);
+        assert_eq!(p2.rating.0, 980.0);
         assert_eq!(p2.default_schedule, false);
     }
 
@@ -175,6 +177,21 @@ mod tests {
         assert!(r.is_err());
     }
 
+    #[test]
+    fn import_error_3() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let r = import(
+            &mut conn,
+            concat!(
+                "{\"players\": [",
+                "{\"name\": \"player1\", \"rating\": \"5k\", \"default_schedule\": false}",
+                "]}"
+            ),
+        );
+        assert!(r.is_err());
+    }
+
     #[test]
     fn export_import_0() {
         let s = {
@@ -4,8 +4,10 @@ use std::str::FromStr;
 use r2d2_sqlite::SqliteConnectionManager;
 
 use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, Value, ValueRef};
+use rusqlite::OptionalExtension;
 
-use crate::models::{GameResult, RoundExtra};
+use crate::models::{GameResult, RoundExtra, Settings};
+use crate::update_ratings::update_ratings;
 
 pub type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
 
@@ -16,14 +18,50 @@ pub fn create_pool(path: &OsStr) -> Result<Pool, r2d2::Error> {
 
 static SCHEMA: &str = include_str!("../database/schema.sql");
 
+/// Tables added to the schema later, to be created in older databases
+static SETTINGS_SCHEMA: &str = "CREATE TABLE settings (id INTEGER PRIMARY KEY, extra JSONB);";
+static GAME_RATINGS_SCHEMA: &str = concat!(
+    "CREATE TABLE game_ratings (game INTEGER PRIMARY KEY REFERENCES games (id), ",
+    "whiterating DOUBLE PRECISION NOT NULL, blackrating DOUBLE PRECISION NOT NULL);"
+);
+
 pub fn ensure_schema(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
     match conn.prepare("SELECT white, black FROM games ORDER BY id") {
-        Ok(_) => Ok(()),
+        Ok(_) => {}
         Err(_) => {
             eprintln!("note: initializing database");
-            conn.execute_batch(SCHEMA)
+            return conn.execute_batch(SCHEMA);
         }
     }
+    if conn.prepare("SELECT extra FROM settings").is_err() {
+        eprintln!("note: adding settings table");
+        conn.execute_batch(SETTINGS_SCHEMA)?;
+    }
+    if conn.prepare("SELECT game FROM game_ratings").is_err() {
+        eprintln!("note: adding game ratings table");
+        let trans = conn.unchecked_transaction()?;
+        trans.execute_batch(GAME_RATINGS_SCHEMA)?;
+        update_ratings(&trans)?;
+        trans.commit()?;
+    }
+    Ok(())
+}
+
+pub fn load_settings(conn: &rusqlite::Connection) -> rusqlite::Result<Settings> {
+    Ok(conn
+        .query_row("SELECT extra FROM settings WHERE id = 1", [], |row| {
+            row.get(0)
+        })
+        .optional()?
+        .unwrap_or_default())
+}
+
+pub fn save_settings(conn: &rusqlite::Connection, settings: &Settings) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (id, extra) VALUES (1, ?1)",
+        [settings],
+    )?;
+    Ok(())
 }
 
 impl FromSql for GameResult {
@@ -49,6 +87,23 @@ impl ToSql for RoundExtra {
     }
 }
 
+impl FromSql for Settings {
+    fn column_result(val: ValueRef) -> Result<Self, FromSqlError> {
+        match val.as_str_or_null()? {
+            None => Ok(Default::default()),
+            Some(s) => serde_json::from_str(s).map_err(|e| FromSqlError::Other(Box::new(e))),
+        }
+    }
+}
+
+impl ToSql for Settings {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let s = serde_json::to_string(self)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Ok(ToSqlOutput::Owned(Value::Text(s)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,4 +174,75 @@ mod tests {
         let conn = rusqlite::Connection::open_in_memory().unwrap();
         ensure_schema(&conn).unwrap();
     }
+
+    #[test]
+    fn upgrade_database_settings() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch("DROP TABLE settings").unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.prepare("SELECT extra FROM settings").unwrap();
+        ensure_schema(&conn).unwrap();
+    }
+
+    #[test]
+    fn upgrade_database_game_ratings() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "DROP TABLE game_ratings;",
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), (42, \"player2\", 1000.0, 1000.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (98, '2019-06-10');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(32, 98, 41, 42, 'WhiteWins');"
+        ))
+        .unwrap();
+        ensure_schema(&conn).unwrap();
+        // The ratings of the existing games are filled in.
+        let rating: f64 = conn
+            .query_row(
+                "SELECT whiterating FROM game_ratings WHERE game = 32",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rating, 1000.0);
+        ensure_schema(&conn).unwrap();
+    }
+
+    #[test]
+    fn settings_default() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.provisional_games, 0);
+        assert_eq!(settings.unknown_fields.len(), 0);
+    }
+
+    #[test]
+    fn settings_save_load() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let mut settings = load_settings(&conn).unwrap();
+        settings.provisional_games = 5;
+        settings
+            .unknown_fields
+            .insert("unknown_field_for_test".to_owned(), json!(8));
+        save_settings(&conn, &settings).unwrap();
+        settings.provisional_factor = 3.0;
+        save_settings(&conn, &settings).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.provisional_games, 5);
+        assert_eq!(settings.provisional_factor, 3.0);
+        assert_eq!(settings.unknown_fields["unknown_field_for_test"], json!(8));
+    }
+
+    #[test]
+    fn settings_from_sql_partial() {
+        let val = ValueRef::Text(b"{\"provisional_games\": 3}");
+        let settings: Settings = FromSql::column_result(val).unwrap();
+        assert_eq!(settings.provisional_games, 3);
+        assert_eq!(settings.provisional_factor, 2.0);
+    }
 }
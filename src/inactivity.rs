@@ -0,0 +1,171 @@
+//! Detection of players who have not taken part in recent rounds
+use std::collections::HashSet;
+
+use rusqlite::params;
+
+use crate::models::{RoundExtra, Settings};
+
+/// Players who neither played a game nor were scheduled in any of the last
+/// `settings.inactive_after_rounds` rounds before `today`. Disabled rounds
+/// are not counted. Nobody is inactive if there have not been enough rounds
+/// yet or the rule is switched off. Players without any game or presence
+/// before those rounds have joined recently and are not inactive either.
+pub fn inactive_players(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+    today: &str,
+) -> rusqlite::Result<HashSet<i32>> {
+    let needed = settings.inactive_after_rounds as usize;
+    if needed == 0 {
+        return Ok(HashSet::new());
+    }
+    let mut stmt =
+        conn.prepare("SELECT id, extra FROM rounds WHERE date < ?1 ORDER BY date DESC")?;
+    let mut rounds = Vec::with_capacity(needed);
+    for row in stmt.query_map(params![today], |row| {
+        let id: i32 = row.get(0)?;
+        let extra: RoundExtra = row.get(1)?;
+        Ok((id, extra.disabled))
+    })? {
+        let (id, disabled) = row?;
+        if !disabled {
+            rounds.push(id);
+            if rounds.len() == needed {
+                break;
+            }
+        }
+    }
+    if rounds.len() < needed {
+        return Ok(HashSet::new());
+    }
+    let first_round = rounds[rounds.len() - 1];
+    let mut stmt = conn.prepare(concat!(
+        "SELECT p.id FROM players p WHERE NOT EXISTS (",
+        "SELECT 1 FROM games g, rounds r WHERE g.played = r.id ",
+        "AND (g.white = p.id OR g.black = p.id) ",
+        "AND r.date >= (SELECT date FROM rounds WHERE id = ?1) AND r.date < ?2",
+        ") AND NOT EXISTS (",
+        "SELECT 1 FROM presence pr, rounds r WHERE pr.\"when\" = r.id ",
+        "AND pr.player = p.id AND pr.schedule ",
+        "AND r.date >= (SELECT date FROM rounds WHERE id = ?1) AND r.date < ?2",
+        ") AND (EXISTS (",
+        "SELECT 1 FROM games g, rounds r WHERE g.played = r.id ",
+        "AND (g.white = p.id OR g.black = p.id) ",
+        "AND r.date < (SELECT date FROM rounds WHERE id = ?1)",
+        ") OR EXISTS (",
+        "SELECT 1 FROM presence pr, rounds r WHERE pr.\"when\" = r.id ",
+        "AND pr.player = p.id AND r.date < (SELECT date FROM rounds WHERE id = ?1)",
+        "))"
+    ))?;
+    let inactive = stmt
+        .query_map(params![first_round, today], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(inactive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    fn setup() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1000.0, 1000.0), ",
+            "(43, \"player3\", 1000.0, 1000.0), ",
+            "(44, \"player4\", 1000.0, 1000.0);",
+            "INSERT INTO rounds (id, \"date\", extra) VALUES ",
+            "(97, '2019-06-03', NULL), ",
+            "(98, '2019-06-10', NULL), ",
+            "(99, '2019-06-17', '{\"disabled\": true}'), ",
+            "(100, '2019-06-24', NULL);",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(31, 97, 41, 42, 'WhiteWins'), ",
+            "(32, 98, 41, 43, 'BlackWins');",
+            "INSERT INTO presence (player, \"when\", schedule) VALUES ",
+            "(44, 98, 1), (42, 98, 0);",
+        ))
+        .unwrap();
+        conn
+    }
+
+    fn ids(list: &[i32]) -> HashSet<i32> {
+        list.iter().cloned().collect()
+    }
+
+    #[test]
+    fn inactive_players_disabled() {
+        let conn = setup();
+        let settings = Settings::default();
+        assert!(inactive_players(&conn, &settings, "2019-07-01")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn inactive_players_1() {
+        let conn = setup();
+        let mut settings = Settings {
+            inactive_after_rounds: 1,
+            ..Settings::default()
+        };
+        assert_eq!(
+            inactive_players(&conn, &settings, "2019-06-11").unwrap(),
+            ids(&[42])
+        );
+        // The disabled round does not count.
+        assert_eq!(
+            inactive_players(&conn, &settings, "2019-06-18").unwrap(),
+            ids(&[42])
+        );
+        assert_eq!(
+            inactive_players(&conn, &settings, "2019-07-01").unwrap(),
+            ids(&[41, 42, 43, 44])
+        );
+        settings.inactive_after_rounds = 2;
+        assert!(inactive_players(&conn, &settings, "2019-06-11")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            inactive_players(&conn, &settings, "2019-07-01").unwrap(),
+            ids(&[42])
+        );
+        settings.inactive_after_rounds = 4;
+        assert!(inactive_players(&conn, &settings, "2019-07-01")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn inactive_players_new_player() {
+        let conn = setup();
+        let settings = Settings {
+            inactive_after_rounds: 1,
+            ..Settings::default()
+        };
+        // A player added after round 98 has not had a chance to play yet.
+        conn.execute(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES (45, \"player5\", 1000.0, 1000.0)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            inactive_players(&conn, &settings, "2019-07-01").unwrap(),
+            ids(&[41, 42, 43, 44])
+        );
+        // Once the player has been in the ladder before the last round, the
+        // rule applies.
+        conn.execute(
+            "INSERT INTO presence (player, \"when\", schedule) VALUES (45, 98, 0)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            inactive_players(&conn, &settings, "2019-07-01").unwrap(),
+            ids(&[41, 42, 43, 44, 45])
+        );
+    }
+}
@@ -5,8 +5,8 @@ use std::sync::Arc;
 
 use actix_multipart::Multipart;
 use actix_web::{
-    body::BoxBody, http, web, web::Data, web::Form, web::Path, App, HttpResponse, HttpServer,
-    Responder, ResponseError,
+    body::BoxBody, http, web, web::Data, web::Form, web::Path, web::Query, App, Either,
+    HttpResponse, HttpServer, Responder, ResponseError,
 };
 use askama::Template;
 use futures_util::TryStreamExt as _;
@@ -14,18 +14,27 @@ use rusqlite::types::ToSql;
 use rusqlite::{params, OptionalExtension};
 use rust_embed::RustEmbed;
 
-use gorating::{Handicap, Rating};
+use gorating::{Handicap, Rank, RankScale, Rating};
 
+mod awards;
+mod calculator;
+mod crosstable;
 mod data_exchange;
 mod db;
+mod inactivity;
 mod models;
 mod presence;
+mod preview;
+mod profile;
+mod settings;
 mod standings;
+mod static_site;
+mod statistics;
 mod update_ratings;
 
 use crate::models::{
-    FormattableGameResult, Game, GameResult, Player, PlayerPresence, PlayerRoundPresence, Round,
-    RoundExtra, RoundPresence, RoundsByMonth,
+    FormattableGameResult, Game, GameResult, Links, Player, PlayerPresence, PlayerRoundPresence,
+    Round, RoundExtra, RoundPresence, RoundsByMonth, Settings,
 };
 
 struct AppState {
@@ -47,6 +56,7 @@ enum Error {
     DataUpload(&'static str),
     ActixWeb(actix_web::Error),
     ActixMultipart(actix_multipart::MultipartError),
+    Template(askama::Error),
 }
 
 impl From<std::io::Error> for Error {
@@ -91,6 +101,12 @@ impl From<actix_multipart::MultipartError> for Error {
     }
 }
 
+impl From<askama::Error> for Error {
+    fn from(e: askama::Error) -> Self {
+        Error::Template(e)
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -103,6 +119,7 @@ impl std::fmt::Display for Error {
             Error::DataUpload(inner) => write!(f, "Data upload: {}", inner),
             Error::ActixWeb(inner) => write!(f, "{}", inner),
             Error::ActixMultipart(inner) => write!(f, "{}", inner),
+            Error::Template(inner) => write!(f, "Template: {}", inner),
         }
     }
 }
@@ -147,6 +164,11 @@ trait CommonTemplate {
     fn version(&self) -> &'static str {
         env!("CARGO_PKG_VERSION")
     }
+
+    /// Where the links in the page lead
+    fn links(&self) -> Links {
+        Links::Live
+    }
 }
 
 #[derive(Template)]
@@ -188,6 +210,10 @@ impl ResponseError for Error {
                 class: "Data upload",
                 message: inner.to_string(),
             },
+            Error::Template(inner) => ErrorTemplate {
+                class: "Template",
+                message: inner.to_string(),
+            },
             Error::ActixWeb(inner) => return inner.error_response(),
             Error::ActixMultipart(inner) => return inner.error_response(),
         };
@@ -239,6 +265,7 @@ struct ScheduleRoundTemplate {
     games: Vec<Game>,
     presences: Vec<RoundPresence>,
     all_players: Vec<Player>,
+    rank_scale: RankScale,
 }
 impl CommonTemplate for ScheduleRoundTemplate {}
 
@@ -265,7 +292,9 @@ async fn schedule_round((params, state): (Path<(i32,)>, Data<AppState>)) -> Resu
             extra: Default::default(),
         });
     let is_past = round.date < today;
-    let mut stmt = conn.prepare("SELECT g.id, pw.id, pw.name, pw.currentrating, pb.id, pb.name, pb.currentrating, g.handicap, g.result FROM players pw, players pb, games g WHERE pw.id = g.white AND pb.id = g.black AND g.played = ?1 ORDER BY g.id")?;
+    let settings = db::load_settings(&conn)?;
+    let sys = settings.rating_system();
+    let mut stmt = conn.prepare("SELECT g.id, pw.id, pw.name, pw.currentrating, pb.id, pb.name, pb.currentrating, g.handicap, g.boardsize, g.result FROM players pw, players pb, games g WHERE pw.id = g.white AND pb.id = g.black AND g.played = ?1 ORDER BY g.id")?;
     let games: Vec<Game> = stmt
         .query_map(&[&round_id], |row| {
             let id: i32 = row.get(0)?;
@@ -276,7 +305,11 @@ async fn schedule_round((params, state): (Path<(i32,)>, Data<AppState>)) -> Resu
             let black: String = row.get(5)?;
             let black_rating = Rating::new(row.get(6)?);
             let handicap = Handicap::new(row.get(7)?);
-            let result: Option<GameResult> = row.get(8)?;
+            let boardsize: u32 = row.get(8)?;
+            let result: Option<GameResult> = row.get(9)?;
+            let board_handicap = sys.board_handicap(handicap.effective(), boardsize);
+            let black_expected = sys.expected_result(black_rating, white_rating, board_handicap);
+            let white_expected = sys.expected_result(white_rating, black_rating, -board_handicap);
             Ok(Game {
                 id,
                 white: Player {
@@ -290,7 +323,10 @@ async fn schedule_round((params, state): (Path<(i32,)>, Data<AppState>)) -> Resu
                     rating: black_rating,
                 },
                 handicap,
+                boardsize,
                 result: FormattableGameResult(result),
+                black_expected,
+                white_expected,
             })
         })?
         .collect::<rusqlite::Result<_>>()?;
@@ -302,7 +338,12 @@ async fn schedule_round((params, state): (Path<(i32,)>, Data<AppState>)) -> Resu
         }
         pairedplayers
     };
-    let mut stmt = conn.prepare("SELECT pl.id, pl.name, pl.currentrating, COALESCE(pr.schedule, pl.defaultschedule) FROM players pl LEFT OUTER JOIN presence pr ON pl.id = pr.player AND pr.\"when\" = ?1 ORDER BY pl.currentrating DESC, pl.id",
+    let skipped = if settings.skip_inactive_schedule {
+        inactivity::inactive_players(&conn, &settings, &today)?
+    } else {
+        HashSet::new()
+    };
+    let mut stmt = conn.prepare("SELECT pl.id, pl.name, pl.currentrating, pr.schedule, pl.defaultschedule FROM players pl LEFT OUTER JOIN presence pr ON pl.id = pr.player AND pr.\"when\" = ?1 ORDER BY pl.currentrating DESC, pl.id",
         )?;
     let presences: Vec<RoundPresence> = if is_past {
         Vec::new()
@@ -311,7 +352,10 @@ async fn schedule_round((params, state): (Path<(i32,)>, Data<AppState>)) -> Resu
             let player_id: i32 = row.get(0)?;
             let name: String = row.get(1)?;
             let rating = Rating::new(row.get(2)?);
-            let schedule: bool = row.get(3)?;
+            let explicit_schedule: Option<bool> = row.get(3)?;
+            let default_schedule: bool = row.get(4)?;
+            let schedule =
+                explicit_schedule.unwrap_or(default_schedule && !skipped.contains(&player_id));
             Ok(if !schedule || pairedplayers.contains(&player_id) {
                 None
             } else {
@@ -348,6 +392,7 @@ async fn schedule_round((params, state): (Path<(i32,)>, Data<AppState>)) -> Resu
         games,
         presences,
         all_players,
+        rank_scale: settings.rank_scale,
     })
 }
 
@@ -390,7 +435,12 @@ fn modify_games(
     Ok(())
 }
 
-fn pair_players(trans: &rusqlite::Transaction, round_id: i32, player_ids: &[i32]) -> Result<()> {
+fn pair_players(
+    trans: &rusqlite::Transaction,
+    round_id: i32,
+    player_ids: &[i32],
+    boardsize: u32,
+) -> Result<()> {
     if player_ids.len() == 0 {
         return Ok(());
     }
@@ -462,9 +512,11 @@ fn pair_players(trans: &rusqlite::Transaction, round_id: i32, player_ids: &[i32]
     eprintln!("weights = {:?}", weights);
     let matching = weightedmatch::weightedmatch(weights, weightedmatch::MINIMIZE);
     eprintln!("matching = {:?}", matching);
+    let settings = db::load_settings(trans)?;
+    let policy = settings.handicap_policy();
     {
         let mut stmt = trans.prepare(
-            "INSERT INTO games (played, white, black, handicap) VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO games (played, white, black, handicap, boardsize) VALUES (?1, ?2, ?3, ?4, ?5)",
         )?;
         for (player, opponent) in matching.iter().skip(1).map(|idx| idx - 1).enumerate() {
             if (ratings[player], player) < (ratings[opponent], opponent) {
@@ -474,13 +526,18 @@ fn pair_players(trans: &rusqlite::Transaction, round_id: i32, player_ids: &[i32]
                 "schedule: {}({}) vs {}({})",
                 player_ids[player], ratings[player], player_ids[opponent], ratings[opponent]
             );
-            let diff = ratings[player] - ratings[opponent];
-            let handicap = update_ratings::RATINGS.calculate_handicap(diff);
+            let handicap = settings.rating_system().calculate_handicap_with_policy(
+                Rating(ratings[opponent]),
+                Rating(ratings[player]),
+                boardsize,
+                &policy,
+            );
             stmt.execute::<&[&dyn ToSql]>(&[
                 &round_id,
                 &player_ids[player],
                 &player_ids[opponent],
                 &handicap.to_f64(),
+                &boardsize,
             ])?;
         }
     }
@@ -491,9 +548,20 @@ struct CustomGame {
     white: i32,
     black: i32,
     handicap: Option<Handicap>,
+    boardsize: u32,
     result: Option<GameResult>,
 }
 
+/// Parse a board size, which must be one of the sizes offered in the forms
+fn parse_boardsize(params: &HashMap<String, String>, name: &'static str) -> Result<u32> {
+    match params.get(name).map(String::as_str) {
+        None | Some("") | Some("19") => Ok(19),
+        Some("13") => Ok(13),
+        Some("9") => Ok(9),
+        Some(_) => Err(Error::BadParam(name)),
+    }
+}
+
 fn parse_custom_game(params: &HashMap<String, String>) -> Result<Option<CustomGame>> {
     let white = match params.get("customwhite") {
         Some(id) => {
@@ -526,6 +594,7 @@ fn parse_custom_game(params: &HashMap<String, String>) -> Result<Option<CustomGa
         }
         None => None,
     };
+    let boardsize = parse_boardsize(params, "customboardsize")?;
     let result_str = match params.get("customresult") {
         Some(s) => s,
         None => "None",
@@ -539,6 +608,7 @@ fn parse_custom_game(params: &HashMap<String, String>) -> Result<Option<CustomGa
         white,
         black,
         handicap,
+        boardsize,
         result,
     }))
 }
@@ -569,7 +639,13 @@ fn add_custom_game(
             .collect::<rusqlite::Result<()>>()?;
             match (white_rating, black_rating) {
                 (Some(w), Some(b)) => {
-                    update_ratings::RATINGS.calculate_handicap(f64::max(w - b, 0.0))
+                    let settings = db::load_settings(trans)?;
+                    settings.rating_system().calculate_handicap_with_policy(
+                        Rating(b),
+                        Rating(f64::max(w, b)),
+                        game.boardsize,
+                        &settings.handicap_policy(),
+                    )
                 }
                 _ => return Err(Error::Inconsistency("one or both players not found")),
             }
@@ -577,12 +653,13 @@ fn add_custom_game(
     };
     let result = game.result.map(GameResult::to_str);
     trans.execute::<&[&dyn ToSql]>(
-        "INSERT INTO games (played, white, black, handicap, result) VALUES (?1, ?2, ?3, ?4, ?5)",
+        "INSERT INTO games (played, white, black, handicap, boardsize, result) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
         &[
             &round_id,
             &game.white,
             &game.black,
             &handicap.to_f64(),
+            &game.boardsize,
             &result,
         ],
     )?;
@@ -657,13 +734,14 @@ async fn schedule_round_run(
             }
         })
         .collect();
+    let boardsize = parse_boardsize(&params.0, "boardsize")?;
     let opt_custom_game = parse_custom_game(&params.0)?;
     let opt_extra = parse_round_extra(&params.0)?;
     let mut conn = state.dbpool.get()?;
     let trans = conn.transaction()?;
     let mut ratings_changed = false;
     modify_games(&trans, round_id, &game_actions, &mut ratings_changed)?;
-    pair_players(&trans, round_id, &player_ids)?;
+    pair_players(&trans, round_id, &player_ids, boardsize)?;
     if let Some(custom_game) = opt_custom_game {
         add_custom_game(&trans, round_id, &custom_game, &mut ratings_changed)?;
     }
@@ -711,9 +789,17 @@ async fn add_round_run(
 #[template(path = "players.html")]
 struct PlayersTemplate {
     players: Vec<Player>,
+    provisional: HashSet<i32>,
+    rank_scale: RankScale,
 }
 impl CommonTemplate for PlayersTemplate {}
 
+impl PlayersTemplate {
+    fn is_provisional(&self, id: &i32) -> bool {
+        self.provisional.contains(id)
+    }
+}
+
 async fn players(state: Data<AppState>) -> Result<impl Responder> {
     let conn = state.dbpool.get()?;
     let mut stmt = conn
@@ -726,7 +812,13 @@ async fn players(state: Data<AppState>) -> Result<impl Responder> {
             Ok(Player { id, name, rating })
         })?
         .collect::<rusqlite::Result<_>>()?;
-    Ok(PlayersTemplate { players })
+    let settings = db::load_settings(&conn)?;
+    let provisional = update_ratings::provisional_players(&conn, &settings)?;
+    Ok(PlayersTemplate {
+        players,
+        provisional,
+        rank_scale: settings.rank_scale,
+    })
 }
 
 #[derive(Template)]
@@ -736,11 +828,14 @@ struct EditPlayerTemplate {
     player: Player,
     presence: PlayerPresence,
     min_rating: f64,
+    rank_scale: RankScale,
 }
 impl CommonTemplate for EditPlayerTemplate {}
 
-async fn add_player(_state: Data<AppState>) -> impl Responder {
-    EditPlayerTemplate {
+async fn add_player(state: Data<AppState>) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    let settings = db::load_settings(&conn)?;
+    Ok(EditPlayerTemplate {
         is_new: true,
         player: Player {
             id: 0,
@@ -751,8 +846,9 @@ async fn add_player(_state: Data<AppState>) -> impl Responder {
             default: true,
             rounds: vec![],
         },
-        min_rating: update_ratings::RATINGS.min_rating.0,
-    }
+        min_rating: settings.min_rating,
+        rank_scale: settings.rank_scale,
+    })
 }
 
 fn update_player_presence(
@@ -805,14 +901,30 @@ fn update_player_presence(
     Ok(())
 }
 
+/// Parse a rating, or a rank on the scale in the settings
+fn parse_initial_rating(s: &str, settings: &Settings) -> Result<f64> {
+    let rating = match f64::from_str(s.trim()) {
+        Ok(rating) if rating.is_finite() => rating,
+        Ok(_) => return Err(Error::BadParam("initialrating")),
+        Err(_) => {
+            let rank = Rank::from_str(s).map_err(|_| Error::BadParam("initialrating"))?;
+            settings.rank_scale.rating(rank).0
+        }
+    };
+    if rating < settings.min_rating {
+        return Err(Error::BadParam("initialrating below minimum rating"));
+    }
+    Ok(rating)
+}
+
 async fn add_player_save(
     (state, params): (Data<AppState>, Form<HashMap<String, String>>),
 ) -> Result<HttpResponse> {
     let name = &params.0["name"];
-    let initialrating =
-        f64::from_str(&params.0["initialrating"]).map_err(|_| Error::BadParam("initialrating"))?;
-    let defaultschedule = params.0.get("defaultschedule").is_some();
     let conn = state.dbpool.get()?;
+    let settings = db::load_settings(&conn)?;
+    let initialrating = parse_initial_rating(&params.0["initialrating"], &settings)?;
+    let defaultschedule = params.0.get("defaultschedule").is_some();
     conn.execute::<&[&dyn ToSql]>(
         "INSERT INTO players (name, initialrating, currentrating, defaultschedule) VALUES (?1, ?2, ?2, ?3)",
         &[&name, &initialrating, &defaultschedule],
@@ -822,25 +934,33 @@ async fn add_player_save(
         .finish())
 }
 
-async fn edit_player((params, state): (Path<(i32,)>, Data<AppState>)) -> Result<impl Responder> {
-    let today = get_today();
-    let player_id = params.0;
-    let conn = state.dbpool.get()?;
+/// Presence of a player in the rounds on or after `today`
+fn upcoming_presence(
+    conn: &rusqlite::Connection,
+    player_id: i32,
+    today: &str,
+) -> rusqlite::Result<Vec<PlayerRoundPresence>> {
     let mut stmt = conn.prepare(concat!(
         "SELECT r.id, CAST(r.date AS TEXT), pr.schedule FROM rounds r ",
         "LEFT OUTER JOIN presence pr ON r.id = pr.\"when\" AND pr.player = ?1 ",
         "WHERE CAST(r.date AS TEXT) >= ?2 ",
         "ORDER BY r.date"
     ))?;
-    let rpresence: Vec<_> = stmt
-        .query_map(params![player_id, today], |row| {
-            Ok(PlayerRoundPresence {
-                round_id: row.get(0)?,
-                round_date: row.get(1)?,
-                schedule: row.get(2)?,
-            })
-        })?
-        .collect::<rusqlite::Result<_>>()?;
+    let rows = stmt.query_map(params![player_id, today], |row| {
+        Ok(PlayerRoundPresence {
+            round_id: row.get(0)?,
+            round_date: row.get(1)?,
+            schedule: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+async fn edit_player((params, state): (Path<(i32,)>, Data<AppState>)) -> Result<impl Responder> {
+    let today = get_today();
+    let player_id = params.0;
+    let conn = state.dbpool.get()?;
+    let rpresence = upcoming_presence(&conn, player_id, &today)?;
     let (player, presence) = conn.query_row(
         "SELECT id, name, initialrating, defaultschedule FROM players WHERE id = ?1",
         &[&player_id],
@@ -858,34 +978,45 @@ async fn edit_player((params, state): (Path<(i32,)>, Data<AppState>)) -> Result<
             ))
         },
     )?;
+    let settings = db::load_settings(&conn)?;
     Ok(EditPlayerTemplate {
         is_new: false,
         player,
         presence,
-        min_rating: update_ratings::RATINGS.min_rating.0,
+        min_rating: settings.min_rating,
+        rank_scale: settings.rank_scale,
     })
 }
 
-async fn edit_player_save(
-    (pathparams, state, params): (Path<(i32,)>, Data<AppState>, Form<HashMap<String, String>>),
-) -> Result<HttpResponse> {
-    let player_id = pathparams.0;
-    let name = &params.0["name"];
-    let initialrating =
-        f64::from_str(&params.0["initialrating"]).map_err(|_| Error::BadParam("initialrating"))?;
-    let defaultschedule = params.0.get("defaultschedule").is_some();
-    let mut conn = state.dbpool.get()?;
-    let trans = conn.transaction()?;
+/// Change a player and recalculate the ratings, without committing
+fn save_player_edit(
+    trans: &rusqlite::Transaction,
+    player_id: i32,
+    params: &HashMap<String, String>,
+) -> Result<()> {
+    let name = params.get("name").ok_or(Error::BadParam("name"))?;
+    let defaultschedule = params.get("defaultschedule").is_some();
+    let settings = db::load_settings(trans)?;
+    let initialrating = parse_initial_rating(
+        params
+            .get("initialrating")
+            .ok_or(Error::BadParam("initialrating"))?,
+        &settings,
+    )?;
     trans.execute::<&[&dyn ToSql]>(
         "UPDATE players SET name = ?1, initialrating = ?2, defaultschedule = ?3 WHERE id = ?4",
         &[&name, &initialrating, &defaultschedule, &player_id],
     )?;
-    update_player_presence(&trans, player_id, &params.0)?;
-    update_ratings::update_ratings(&trans)?;
-    trans.commit()?;
-    Ok(HttpResponse::Found()
-        .append_header((http::header::LOCATION, "/players"))
-        .finish())
+    update_player_presence(trans, player_id, params)?;
+    update_ratings::update_ratings(trans)?;
+    Ok(())
+}
+
+async fn edit_player_save(
+    (pathparams, state, params): (Path<(i32,)>, Data<AppState>, Form<HashMap<String, String>>),
+) -> Result<Either<preview::EditPlayerPreviewTemplate, HttpResponse>> {
+    let mut conn = state.dbpool.get()?;
+    preview::submit_player_edit(&mut conn, pathparams.0, &params.0)
 }
 
 async fn export(state: Data<AppState>) -> Result<HttpResponse> {
@@ -917,9 +1048,28 @@ async fn import((state, mut payload): (Data<AppState>, Multipart)) -> Result<imp
     Ok(result)
 }
 
-async fn standings_page(state: Data<AppState>) -> Result<impl Responder> {
+async fn standings_page(
+    (state, params): (Data<AppState>, Query<HashMap<String, String>>),
+) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    standings::standings(&conn, &params.0)
+}
+
+async fn crosstable_page(state: Data<AppState>) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    crosstable::crosstable(&conn)
+}
+
+async fn crosstable_pair_page(
+    (params, state): (Path<(i32, i32)>, Data<AppState>),
+) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    crosstable::pair(&conn, params.0, params.1)
+}
+
+async fn profile_page((params, state): (Path<(i32,)>, Data<AppState>)) -> Result<impl Responder> {
     let conn = state.dbpool.get()?;
-    standings::standings(&conn)
+    profile::profile(&conn, params.0)
 }
 
 async fn presence_page(state: Data<AppState>) -> Result<impl Responder> {
@@ -927,6 +1077,38 @@ async fn presence_page(state: Data<AppState>) -> Result<impl Responder> {
     presence::presence(&conn)
 }
 
+async fn settings_page(state: Data<AppState>) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    settings::settings(&conn)
+}
+
+async fn settings_save(
+    (state, params): (Data<AppState>, Form<HashMap<String, String>>),
+) -> Result<HttpResponse> {
+    let mut conn = state.dbpool.get()?;
+    settings::settings_save(&mut conn, &params.0)?;
+    Ok(HttpResponse::Found()
+        .append_header((http::header::LOCATION, "/settings"))
+        .finish())
+}
+
+async fn statistics_page(state: Data<AppState>) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    statistics::statistics(&conn)
+}
+
+async fn awards_page(state: Data<AppState>) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    awards::awards(&conn)
+}
+
+async fn calculator_page(
+    (state, params): (Data<AppState>, Query<HashMap<String, String>>),
+) -> Result<impl Responder> {
+    let conn = state.dbpool.get()?;
+    calculator::calculator(&conn, &params.0)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let dbpath = std::env::args_os().nth(1).unwrap_or_else(|| {
@@ -938,6 +1120,23 @@ async fn main() -> Result<()> {
         let conn = dbpool.get()?;
         db::ensure_schema(&conn)?;
     }
+    if let Some(command) = std::env::args_os().nth(2) {
+        if command != "export-static" {
+            eprintln!(
+                "Unknown command {:?}, the only command is \"export-static\"",
+                command
+            );
+            std::process::exit(2);
+        }
+        let dir = std::env::args_os().nth(3).unwrap_or_else(|| {
+            eprintln!("Need an output directory for export-static");
+            std::process::exit(2);
+        });
+        let conn = dbpool.get()?;
+        let count = static_site::export(&conn, std::path::Path::new(&dir), &get_today())?;
+        println!("Wrote {} files to {}", count, dir.to_string_lossy());
+        return Ok(());
+    }
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(AppState {
@@ -953,10 +1152,21 @@ async fn main() -> Result<()> {
             .route("/add_player", web::post().to(add_player_save))
             .route("/player/{id}", web::get().to(edit_player))
             .route("/player/{id}", web::post().to(edit_player_save))
+            .route("/profile/{id}", web::get().to(profile_page))
             .route("/export", web::get().to(export))
             .route("/import", web::post().to(import))
             .route("/standings", web::get().to(standings_page))
+            .route("/crosstable", web::get().to(crosstable_page))
+            .route(
+                "/crosstable/{player}/{opponent}",
+                web::get().to(crosstable_pair_page),
+            )
             .route("/presence", web::get().to(presence_page))
+            .route("/statistics", web::get().to(statistics_page))
+            .route("/awards", web::get().to(awards_page))
+            .route("/calculator", web::get().to(calculator_page))
+            .route("/settings", web::get().to(settings_page))
+            .route("/settings", web::post().to(settings_save))
             .route("/static/{path:.*}", web::get().to(static_asset))
     })
     .bind("127.0.0.1:8080")
@@ -970,6 +1180,63 @@ async fn main() -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_initial_rating_1() {
+        let settings = Settings::default();
+        assert_eq!(parse_initial_rating("1100", &settings).unwrap(), 1100.0);
+        assert_eq!(parse_initial_rating(" 1234.5 ", &settings).unwrap(), 1234.5);
+        assert_eq!(parse_initial_rating("-900", &settings).unwrap(), -900.0);
+        assert_eq!(parse_initial_rating("5k", &settings).unwrap(), 1600.0);
+        assert_eq!(parse_initial_rating("5 kyu", &settings).unwrap(), 1600.0);
+        assert_eq!(parse_initial_rating("2 dan", &settings).unwrap(), 2200.0);
+    }
+
+    #[test]
+    fn parse_initial_rating_rank_scale() {
+        let mut settings = Settings {
+            rank_scale: RankScale::Aga,
+            ..Settings::default()
+        };
+        assert_eq!(parse_initial_rating("1100", &settings).unwrap(), 1100.0);
+        assert_eq!(parse_initial_rating("5k", &settings).unwrap(), 1500.0);
+        assert_eq!(parse_initial_rating("1d", &settings).unwrap(), 2000.0);
+        settings.rank_scale = RankScale::Kgs;
+        assert_eq!(parse_initial_rating("1d", &settings).unwrap(), 1900.0);
+        assert!(parse_initial_rating("30k", &settings).is_err());
+    }
+
+    #[test]
+    fn parse_initial_rating_errors() {
+        let mut settings = Settings::default();
+        assert!(parse_initial_rating("", &settings).is_err());
+        assert!(parse_initial_rating("strong", &settings).is_err());
+        assert!(parse_initial_rating("-1000", &settings).is_err());
+        for s in &["NaN", "nan", "inf", "-inf", "infinity"] {
+            assert!(parse_initial_rating(s, &settings).is_err());
+        }
+        settings.min_rating = 100.0;
+        assert!(parse_initial_rating("50", &settings).is_err());
+    }
+
+    #[test]
+    fn parse_boardsize_1() {
+        let mut params = HashMap::new();
+        assert_eq!(parse_boardsize(&params, "boardsize").unwrap(), 19);
+        for &size in &["", "19", "13", "9"] {
+            params.insert("boardsize".to_owned(), size.to_owned());
+            let expected = if size.is_empty() {
+                19
+            } else {
+                size.parse().unwrap()
+            };
+            assert_eq!(parse_boardsize(&params, "boardsize").unwrap(), expected);
+        }
+        for &size in &["7", "21", "nine"] {
+            params.insert("boardsize".to_owned(), size.to_owned());
+            assert!(parse_boardsize(&params, "boardsize").is_err());
+        }
+    }
+
     #[test]
     fn get_today_1() {
         let s = get_today();
@@ -3,7 +3,7 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
-use gorating::{Handicap, Rating};
+use gorating::{Formula, Handicap, HandicapPolicy, RankScale, Rating, RatingSystem};
 
 #[derive(Debug)]
 pub struct Player {
@@ -56,6 +56,28 @@ pub enum OneSidedGameResult {
     LoseByDefault,
 }
 
+impl OneSidedGameResult {
+    /// Points for a game that was played, or `None` for a forfeit
+    pub fn played_score(self) -> Option<f64> {
+        match self {
+            OneSidedGameResult::Win => Some(1.0),
+            OneSidedGameResult::Jigo => Some(0.5),
+            OneSidedGameResult::Lose => Some(0.0),
+            OneSidedGameResult::WinByDefault | OneSidedGameResult::LoseByDefault => None,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            OneSidedGameResult::Win => "win",
+            OneSidedGameResult::Lose => "loss",
+            OneSidedGameResult::Jigo => "jigo",
+            OneSidedGameResult::WinByDefault => "win by default",
+            OneSidedGameResult::LoseByDefault => "loss by default",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BadGameResult;
 
@@ -161,7 +183,36 @@ pub struct Game {
     pub white: Player,
     pub black: Player,
     pub handicap: Handicap,
+    pub boardsize: u32,
     pub result: FormattableGameResult,
+    /// Expected result for black according to the rating system
+    pub black_expected: f64,
+    /// Expected result for white, which is not `1 - black_expected` in
+    /// formulas where part of the expected result goes to neither player
+    pub white_expected: f64,
+}
+
+impl Game {
+    pub fn is_small_board(&self) -> bool {
+        self.boardsize != 19
+    }
+
+    pub fn black_win_chance(&self) -> Percentage {
+        Percentage(self.black_expected)
+    }
+
+    pub fn white_win_chance(&self) -> Percentage {
+        Percentage(self.white_expected)
+    }
+}
+
+/// A fraction formatted as a whole percentage
+pub struct Percentage(pub f64);
+
+impl std::fmt::Display for Percentage {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}%", (self.0 * 100.0).round())
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -177,6 +228,13 @@ impl Colour {
             Colour::White => 'w',
         }
     }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colour::Black => "black",
+            Colour::White => "white",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -184,7 +242,10 @@ pub struct OneSidedGame {
     pub id: i32,
     pub colour: Colour,
     pub other_place: usize,
+    /// Effective handicap, rounded to half stones to keep the table compact
     pub handicap: f64,
+    /// Handicap as stored for the game
+    pub stored_handicap: Handicap,
     pub result: OneSidedGameResult,
 }
 
@@ -217,6 +278,157 @@ impl RoundExtra {
     }
 }
 
+/// Rating formula as stored in the settings. Settings saved before the
+/// formula could be chosen use the 2021 formula, which was the only one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingFormula {
+    Egd2021,
+    EgdLegacy,
+}
+
+impl RatingFormula {
+    pub fn to_formula(self) -> Formula {
+        match self {
+            RatingFormula::Egd2021 => Formula::Egd2021,
+            RatingFormula::EgdLegacy => Formula::EgdLegacy,
+        }
+    }
+}
+
+impl FromStr for RatingFormula {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "egd2021" => Ok(RatingFormula::Egd2021),
+            "egd_legacy" => Ok(RatingFormula::EgdLegacy),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Period within which the maximum rating drop applies. Games are always
+/// rated round by round; a longer period only limits the total drop over
+/// its rounds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingPeriod {
+    Round,
+    Month,
+    Season,
+}
+
+impl RatingPeriod {
+    /// Key that is the same for all rounds in the same period
+    pub fn key(self, round_id: i32, date: &str) -> (i32, &str) {
+        match self {
+            RatingPeriod::Round => (round_id, ""),
+            RatingPeriod::Month => (0, date.get(..7).unwrap_or(date)),
+            RatingPeriod::Season => (0, ""),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RatingPeriod::Round => "round",
+            RatingPeriod::Month => "month",
+            RatingPeriod::Season => "season",
+        }
+    }
+}
+
+impl FromStr for RatingPeriod {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round" => Ok(RatingPeriod::Round),
+            "month" => Ok(RatingPeriod::Month),
+            "season" => Ok(RatingPeriod::Season),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings for the season, stored as JSON in the settings table
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Number of rated games for which a new player's rating is provisional
+    pub provisional_games: u32,
+    /// Factor applied to rating changes while the rating is provisional
+    pub provisional_factor: f64,
+    /// Number of stones by which the standard handicap is reduced
+    pub handicap_reduction: f64,
+    /// Maximum number of handicap stones, if any
+    pub max_handicap: Option<u32>,
+    /// Rating from which players no longer receive handicap, if any
+    pub no_handicap_above: Option<f64>,
+    /// Number of rounds without games or presence after which a player is
+    /// inactive, or 0 to never mark players inactive
+    pub inactive_after_rounds: u32,
+    /// Leave inactive players out of the standings instead of greying them
+    pub hide_inactive: bool,
+    /// Do not schedule inactive players by default
+    pub skip_inactive_schedule: bool,
+    /// Scale on which ranks are displayed and entered
+    pub rank_scale: RankScale,
+    pub rating_formula: RatingFormula,
+    pub bonus_factor: f64,
+    pub min_rating: f64,
+    /// Maximum rating points that a player can lose in one period, if any
+    pub max_drop: Option<f64>,
+    pub max_drop_period: RatingPeriod,
+    /// Number of played games from which a player is eligible for any of
+    /// the awards
+    pub award_min_games: u32,
+    #[serde(flatten)]
+    pub unknown_fields: HashMap<String, serde_json::Value>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            provisional_games: 0,
+            provisional_factor: 2.0,
+            handicap_reduction: 0.0,
+            max_handicap: None,
+            no_handicap_above: None,
+            inactive_after_rounds: 0,
+            hide_inactive: false,
+            skip_inactive_schedule: false,
+            rank_scale: RankScale::Egd,
+            rating_formula: RatingFormula::Egd2021,
+            bonus_factor: 0.2,
+            min_rating: -900.0,
+            max_drop: Some(100.0),
+            max_drop_period: RatingPeriod::Round,
+            award_min_games: 5,
+            unknown_fields: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn rating_system(&self) -> RatingSystem {
+        RatingSystem {
+            formula: self.rating_formula.to_formula(),
+            bonus_factor: self.bonus_factor,
+            min_rating: Rating(self.min_rating),
+            max_drop: self.max_drop.unwrap_or(f64::INFINITY),
+        }
+    }
+
+    pub fn handicap_policy(&self) -> HandicapPolicy {
+        HandicapPolicy {
+            reduction: self.handicap_reduction,
+            max_stones: self.max_handicap,
+            no_handicap_above: self.no_handicap_above.map(Rating),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Round {
     pub id: i32,
@@ -230,6 +442,72 @@ impl Round {
     }
 }
 
+/// Where the links in a page lead: to the pages of the application, or to
+/// the files of the static website, relative to `root`
+#[derive(Debug, Copy, Clone)]
+pub enum Links {
+    Live,
+    Static { root: &'static str },
+}
+
+impl Links {
+    pub fn is_static(&self) -> bool {
+        matches!(self, Links::Static { .. })
+    }
+
+    /// An embedded asset such as `css/default.css`
+    pub fn asset(&self, path: &str) -> String {
+        match self {
+            Links::Live => format!("/static/{}", path),
+            Links::Static { root } => format!("{}static/{}", root, path),
+        }
+    }
+
+    pub fn rounds(&self) -> String {
+        match self {
+            Links::Live => "/".to_owned(),
+            Links::Static { root } => format!("{}index.html", root),
+        }
+    }
+
+    pub fn standings(&self) -> String {
+        match self {
+            Links::Live => "/standings".to_owned(),
+            Links::Static { root } => format!("{}standings.html", root),
+        }
+    }
+
+    pub fn crosstable(&self) -> String {
+        match self {
+            Links::Live => "/crosstable".to_owned(),
+            Links::Static { root } => format!("{}crosstable.html", root),
+        }
+    }
+
+    pub fn round(&self, round_id: &i32) -> String {
+        match self {
+            Links::Live => format!("/schedule/{}", round_id),
+            Links::Static { root } => format!("{}rounds/{}.html", root, round_id),
+        }
+    }
+
+    pub fn profile(&self, player_id: &i32) -> String {
+        match self {
+            Links::Live => format!("/profile/{}", player_id),
+            Links::Static { root } => format!("{}players/{}.html", root, player_id),
+        }
+    }
+
+    /// The games between two players, which have no page of their own in
+    /// the static website
+    pub fn pair(&self, player_id: &i32, opponent_id: &i32) -> Option<String> {
+        match self {
+            Links::Live => Some(format!("/crosstable/{}/{}", player_id, opponent_id)),
+            Links::Static { .. } => None,
+        }
+    }
+}
+
 /// Rounds, which will be iterated over grouped by month
 #[derive(Debug)]
 pub struct RoundsByMonth(pub Vec<Round>);
@@ -286,12 +564,56 @@ pub struct StandingsPlayer {
     pub default_schedule: bool,
     pub initialrating: Rating,
     pub currentrating: Rating,
+    pub provisional: bool,
+    pub inactive: bool,
     pub results: Vec<Vec<OneSidedGame>>,
     pub score: f64,
     pub games: i64,
+    /// Sum of the scores of the opponents in played games
+    pub sos: f64,
+    /// Sum of the scores of the opponents defeated in played games, with
+    /// half for jigo
+    pub sodos: f64,
+}
+
+/// Order of the players in the standings
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StandingsOrder {
+    Rating,
+    Score,
+    /// Score per game, for players with a minimum number of games
+    WinPercentage,
+    /// Score with SOS, SODOS and the results between the tied players as
+    /// tiebreakers
+    Points,
 }
 
-pub struct PlaceDiff(isize);
+impl FromStr for StandingsOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rating" => Ok(StandingsOrder::Rating),
+            "score" => Ok(StandingsOrder::Score),
+            "percentage" => Ok(StandingsOrder::WinPercentage),
+            "points" => Ok(StandingsOrder::Points),
+            _ => Err(()),
+        }
+    }
+}
+
+impl StandingsOrder {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            StandingsOrder::Rating => "rating",
+            StandingsOrder::Score => "score",
+            StandingsOrder::WinPercentage => "percentage",
+            StandingsOrder::Points => "points",
+        }
+    }
+}
+
+pub struct PlaceDiff(pub isize);
 
 impl std::fmt::Display for PlaceDiff {
     fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -304,7 +626,8 @@ impl std::fmt::Display for PlaceDiff {
     }
 }
 
-pub struct RatingDiff(f64);
+#[derive(Debug, Copy, Clone)]
+pub struct RatingDiff(pub f64);
 
 impl std::fmt::Display for RatingDiff {
     fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -338,6 +661,51 @@ impl StandingsPlayer {
     pub fn rating_diff(&self) -> RatingDiff {
         RatingDiff(self.currentrating - self.initialrating)
     }
+
+    pub fn win_percentage(&self) -> Option<Percentage> {
+        if self.games > 0 {
+            Some(Percentage(self.score / self.games as f64))
+        } else {
+            None
+        }
+    }
+}
+
+/// Rating change of one player for one possible result of a game
+pub struct WhatIfResult {
+    pub result: &'static str,
+    /// Adjustment calculated by the rating system
+    pub adjustment: f64,
+    /// Adjustment after limiting the maximum drop
+    pub limited_adjustment: f64,
+    pub new_rating: Rating,
+}
+
+pub struct WhatIfPlayer {
+    pub name: String,
+    pub colour: Colour,
+    pub rating: Rating,
+    /// Whether the provisional factor applies to the rating changes
+    pub provisional: bool,
+    pub expected: f64,
+    pub bonus: f64,
+    pub results: Vec<WhatIfResult>,
+}
+
+pub struct WhatIf {
+    pub handicap: Handicap,
+    /// Black and white, in that order
+    pub players: [WhatIfPlayer; 2],
+}
+
+impl WhatIf {
+    pub fn black(&self) -> &WhatIfPlayer {
+        &self.players[0]
+    }
+
+    pub fn white(&self) -> &WhatIfPlayer {
+        &self.players[1]
+    }
 }
 
 #[derive(Clone)]
@@ -356,6 +724,7 @@ pub struct PresencePlayer {
     pub id: i32,
     pub name: String,
     pub default: bool,
+    pub inactive: bool,
     pub presences: Vec<PresencePlayerRound>,
 }
 
@@ -427,6 +796,14 @@ mod test {
         assert_eq!(r[1].rounds[2].date, "2019-07-15");
     }
 
+    #[test]
+    fn test_percentage() {
+        assert_eq!(Percentage(0.0).to_string(), "0%");
+        assert_eq!(Percentage(0.5).to_string(), "50%");
+        assert_eq!(Percentage(0.6666).to_string(), "67%");
+        assert_eq!(Percentage(1.0).to_string(), "100%");
+    }
+
     #[test]
     fn test_rating_diff_1() {
         let p = StandingsPlayer {
@@ -436,9 +813,13 @@ mod test {
             default_schedule: false,
             initialrating: Rating::new(1000.0),
             currentrating: Rating::new(1100.3),
+            provisional: false,
+            inactive: false,
             results: vec![],
             score: 3.0,
             games: 5,
+            sos: 0.0,
+            sodos: 0.0,
         };
         assert_eq!(&format!("{}", p.rating_diff()), "+100");
     }
@@ -452,9 +833,13 @@ mod test {
             default_schedule: false,
             initialrating: Rating::new(1001.0),
             currentrating: Rating::new(990.0),
+            provisional: false,
+            inactive: false,
             results: vec![],
             score: 0.0,
             games: 1,
+            sos: 0.0,
+            sodos: 0.0,
         };
         assert_eq!(&format!("{}", p.rating_diff()), "−11");
     }
@@ -468,9 +853,13 @@ mod test {
             default_schedule: false,
             initialrating: Rating::new(1001.0),
             currentrating: Rating::new(1000.6),
+            provisional: false,
+            inactive: false,
             results: vec![],
             score: 0.5,
             games: 1,
+            sos: 0.0,
+            sodos: 0.0,
         };
         assert_eq!(&format!("{}", p.rating_diff()), "−0");
     }
@@ -5,6 +5,8 @@ use actix_web::Responder;
 use askama::Template;
 use rusqlite::params;
 
+use crate::db::load_settings;
+use crate::inactivity::inactive_players;
 use crate::models::{PresencePlayer, PresencePlayerRound, Round, RoundsByMonth};
 use crate::{get_today, CommonTemplate, Error, Result};
 
@@ -46,14 +48,20 @@ fn presence_internal(conn: &rusqlite::Connection, today: String) -> Result<Prese
             presence: None,
         })
         .collect();
+    let settings = load_settings(conn)?;
+    let inactive = inactive_players(conn, &settings, &today)?;
     let mut stmt = conn
         .prepare("SELECT id, name, defaultschedule FROM players ORDER BY currentrating DESC, id")?;
     let mut players: Vec<PresencePlayer> = stmt
         .query_map([], |row| {
+            let id: i32 = row.get(0)?;
+            let default: bool = row.get(2)?;
+            let inactive = inactive.contains(&id);
             Ok(PresencePlayer {
-                id: row.get(0)?,
+                id,
                 name: row.get(1)?,
-                default: row.get(2)?,
+                default: default && !(inactive && settings.skip_inactive_schedule),
+                inactive,
                 presences: default_presences.clone(),
             })
         })?
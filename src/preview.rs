@@ -0,0 +1,258 @@
+//! Preview of the rating changes caused by editing a player
+use std::collections::HashMap;
+
+use actix_web::{http, Either, HttpResponse};
+use askama::Template;
+
+use gorating::Rating;
+
+use crate::db::load_settings;
+use crate::inactivity::inactive_players;
+use crate::{get_today, save_player_edit, CommonTemplate, Result};
+
+/// A player whose rating or place in the standings would change
+#[derive(Debug)]
+pub struct PlayerChange {
+    pub id: i32,
+    pub name: String,
+    pub old_rating: Rating,
+    pub new_rating: Rating,
+    pub old_place: usize,
+    pub new_place: usize,
+}
+
+#[derive(Template)]
+#[template(path = "edit_player_preview.html")]
+pub(crate) struct EditPlayerPreviewTemplate {
+    player_id: i32,
+    name: String,
+    changes: Vec<PlayerChange>,
+    /// Form parameters to submit again when the edit is confirmed
+    params: Vec<(String, String)>,
+}
+impl CommonTemplate for EditPlayerPreviewTemplate {}
+
+/// Name, rating and place of every player, keyed by id, in the order of the
+/// standings
+fn ladder(
+    conn: &rusqlite::Connection,
+    today: &str,
+) -> Result<HashMap<i32, (String, Rating, usize)>> {
+    let settings = load_settings(conn)?;
+    let inactive = inactive_players(conn, &settings, today)?;
+    let mut stmt = conn
+        .prepare("SELECT id, name, currentrating FROM players ORDER BY currentrating DESC, id")?;
+    let mut players: Vec<(i32, String, Rating)> = stmt
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, Rating::new(row.get(2)?)))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    // Inactive players are placed after all active players, as in the
+    // standings.
+    players.sort_by_key(|p| inactive.contains(&p.0));
+    Ok(players
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (id, name, rating))| (id, (name, rating, idx + 1)))
+        .collect())
+}
+
+/// Apply the edit without committing it and list the players affected by it,
+/// ordered by their new place
+fn player_changes(
+    conn: &mut rusqlite::Connection,
+    player_id: i32,
+    params: &HashMap<String, String>,
+    today: &str,
+) -> Result<Vec<PlayerChange>> {
+    let trans = conn.transaction()?;
+    let before = ladder(&trans, today)?;
+    save_player_edit(&trans, player_id, params)?;
+    let after = ladder(&trans, today)?;
+    trans.rollback()?;
+    let mut changes: Vec<PlayerChange> = after
+        .into_iter()
+        .filter_map(|(id, (name, new_rating, new_place))| {
+            let (_, old_rating, old_place) = before[&id];
+            if (new_rating.0 - old_rating.0).abs() < 1e-6 && new_place == old_place {
+                return None;
+            }
+            Some(PlayerChange {
+                id,
+                name,
+                old_rating,
+                new_rating,
+                old_place,
+                new_place,
+            })
+        })
+        .collect();
+    changes.sort_by_key(|c| c.new_place);
+    Ok(changes)
+}
+
+pub(crate) fn preview_player_edit(
+    conn: &mut rusqlite::Connection,
+    player_id: i32,
+    params: &HashMap<String, String>,
+) -> Result<EditPlayerPreviewTemplate> {
+    let changes = player_changes(conn, player_id, params, &get_today())?;
+    let mut params: Vec<(String, String)> = params
+        .iter()
+        .filter(|(k, _)| k.as_str() != "confirm")
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    params.sort();
+    let name = conn.query_row(
+        "SELECT name FROM players WHERE id = ?1",
+        [player_id],
+        |row| row.get(0),
+    )?;
+    Ok(EditPlayerPreviewTemplate {
+        player_id,
+        name,
+        changes,
+        params,
+    })
+}
+
+/// Handle a submission of the form for editing a player. The edit is only
+/// saved when it was confirmed from the preview; otherwise the preview is
+/// shown.
+pub(crate) fn submit_player_edit(
+    conn: &mut rusqlite::Connection,
+    player_id: i32,
+    params: &HashMap<String, String>,
+) -> Result<Either<EditPlayerPreviewTemplate, HttpResponse>> {
+    if !params.contains_key("confirm") {
+        let template = preview_player_edit(conn, player_id, params)?;
+        return Ok(Either::Left(template));
+    }
+    let trans = conn.transaction()?;
+    save_player_edit(&trans, player_id, params)?;
+    trans.commit()?;
+    Ok(Either::Right(
+        HttpResponse::Found()
+            .append_header((http::header::LOCATION, "/players"))
+            .finish(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+    use crate::update_ratings::update_ratings;
+
+    fn setup() -> rusqlite::Connection {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1050.0, 1050.0), ",
+            "(43, \"player3\", 2000.0, 2000.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, handicap, result) VALUES ",
+            "(32, 99, 42, 41, 0.0, 'WhiteWins');"
+        ))
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        conn
+    }
+
+    fn edit(name: &str, initialrating: &str) -> HashMap<String, String> {
+        let mut params = HashMap::new();
+        params.insert("name".to_owned(), name.to_owned());
+        params.insert("initialrating".to_owned(), initialrating.to_owned());
+        params.insert("defaultschedule".to_owned(), "on".to_owned());
+        params
+    }
+
+    fn current_rating(conn: &rusqlite::Connection, id: i32) -> f64 {
+        conn.query_row(
+            "SELECT currentrating FROM players WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn player_changes_none() {
+        let mut conn = setup();
+        let changes = player_changes(&mut conn, 43, &edit("player3", "2000"), "2019-07-01");
+        assert!(changes.unwrap().is_empty());
+    }
+
+    #[test]
+    fn player_changes_1() {
+        let mut conn = setup();
+        let old41 = current_rating(&conn, 41);
+        let old42 = current_rating(&conn, 42);
+        let changes =
+            player_changes(&mut conn, 41, &edit("player1", "1500"), "2019-07-01").unwrap();
+        // Player 2 gains more for beating a stronger player, and player 1
+        // moves above player 2.
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].id, 41);
+        assert_eq!(changes[0].old_place, 3);
+        assert_eq!(changes[0].new_place, 2);
+        assert_eq!(changes[0].old_rating.0, old41);
+        assert!(changes[0].new_rating.0 > 1400.0);
+        assert_eq!(changes[1].id, 42);
+        assert_eq!(changes[1].old_rating.0, old42);
+        assert!(changes[1].new_rating.0 > old42);
+        // Nothing is saved.
+        assert_eq!(current_rating(&conn, 41), old41);
+        let initial: f64 = conn
+            .query_row(
+                "SELECT initialrating FROM players WHERE id = 41",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(initial, 1000.0);
+    }
+
+    #[test]
+    fn player_changes_bad_param() {
+        let mut conn = setup();
+        assert!(player_changes(&mut conn, 41, &edit("player1", "x"), "2019-07-01").is_err());
+        assert_eq!(current_rating(&conn, 43), 2000.0);
+    }
+
+    #[test]
+    fn submit_player_edit_confirm() {
+        let mut conn = setup();
+        let old41 = current_rating(&conn, 41);
+        let mut params = edit("player1", "1500");
+        // The first submission only shows the preview.
+        match submit_player_edit(&mut conn, 41, &params).unwrap() {
+            Either::Left(template) => {
+                assert_eq!(template.changes.len(), 2);
+                assert!(template
+                    .params
+                    .contains(&("initialrating".to_owned(), "1500".to_owned())));
+            }
+            Either::Right(_) => panic!("saved without confirmation"),
+        }
+        assert_eq!(current_rating(&conn, 41), old41);
+        let initial = |conn: &rusqlite::Connection| -> f64 {
+            conn.query_row(
+                "SELECT initialrating FROM players WHERE id = 41",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(initial(&conn), 1000.0);
+        params.insert("confirm".to_owned(), "Confirm".to_owned());
+        let response = submit_player_edit(&mut conn, 41, &params).unwrap();
+        assert!(matches!(response, Either::Right(_)));
+        assert_eq!(initial(&conn), 1500.0);
+        assert!(current_rating(&conn, 41) > 1400.0);
+    }
+}
@@ -0,0 +1,411 @@
+//! Read-only profile of a player with the full game history
+use std::collections::HashMap;
+
+use actix_web::Responder;
+use askama::Template;
+
+use gorating::{Handicap, RankScale, Rating};
+
+use crate::crosstable::Record;
+use crate::db::load_settings;
+use crate::models::{GameResult, Links, OneSidedGameResult, Player, PlayerPresence, RatingDiff};
+use crate::update_ratings::{performance_ratings, rated_games, RatedGame};
+use crate::{get_today, upcoming_presence, CommonTemplate, Result};
+
+/// A game seen from the side of the player of the profile
+#[derive(Debug)]
+pub struct ProfileGame {
+    pub round_id: i32,
+    pub date: String,
+    pub opponent_id: i32,
+    pub opponent_name: String,
+    pub is_black: bool,
+    pub handicap: Handicap,
+    pub boardsize: u32,
+    pub result: Option<OneSidedGameResult>,
+    /// Rating change before the maximum drop of the period is applied, for
+    /// rated games only
+    pub rating_change: Option<RatingDiff>,
+}
+
+impl ProfileGame {
+    pub fn colour(&self) -> &'static str {
+        if self.is_black {
+            "black"
+        } else {
+            "white"
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.result.map_or("not played yet", |r| r.description())
+    }
+}
+
+/// Record in the games with one handicap and board size, with the player
+/// either receiving the handicap as black or giving it as white
+#[derive(Debug)]
+pub struct HandicapRecord {
+    pub handicap: Handicap,
+    pub boardsize: u32,
+    pub is_black: bool,
+    pub record: Record,
+}
+
+impl HandicapRecord {
+    pub fn colour(&self) -> &'static str {
+        if self.is_black {
+            "black"
+        } else {
+            "white"
+        }
+    }
+}
+
+/// Number of games with results against one opponent
+#[derive(Debug)]
+pub struct OpponentRecord {
+    pub id: i32,
+    pub name: String,
+    pub games: u32,
+    pub record: Record,
+}
+
+/// Run of identical results in the latest played games, leaving out forfeits
+#[derive(Debug, PartialEq, Eq)]
+pub struct Streak {
+    pub result: OneSidedGameResult,
+    pub length: usize,
+}
+
+impl std::fmt::Display for Streak {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (one, many) = match self.result {
+            OneSidedGameResult::Win => ("win", "wins"),
+            OneSidedGameResult::Lose => ("loss", "losses"),
+            _ => ("jigo", "jigos"),
+        };
+        let noun = if self.length == 1 { one } else { many };
+        write!(formatter, "{} {}", self.length, noun)
+    }
+}
+
+/// Number of opponents shown on the profile
+const FREQUENT_OPPONENTS: usize = 5;
+
+#[derive(Template)]
+#[template(path = "profile.html")]
+struct ProfileTemplate {
+    player: Player,
+    initial_rating: Rating,
+    /// Performance rating over all rated games
+    performance: Option<Rating>,
+    rank_scale: RankScale,
+    games: Vec<ProfileGame>,
+    black: Record,
+    white: Record,
+    handicaps: Vec<HandicapRecord>,
+    opponents: Vec<OpponentRecord>,
+    streak: Option<Streak>,
+    presence: PlayerPresence,
+    links: Links,
+}
+impl CommonTemplate for ProfileTemplate {
+    fn links(&self) -> Links {
+        self.links
+    }
+}
+
+pub(crate) fn profile(conn: &rusqlite::Connection, player_id: i32) -> Result<impl Responder> {
+    profile_internal(conn, player_id, &get_today())
+}
+
+/// Render the profile of a player for the static website
+pub(crate) fn render_static(
+    conn: &rusqlite::Connection,
+    player_id: i32,
+    today: &str,
+) -> Result<String> {
+    let mut pt = profile_internal(conn, player_id, today)?;
+    pt.links = Links::Static { root: "../" };
+    Ok(pt.render()?)
+}
+
+fn load_games(
+    conn: &rusqlite::Connection,
+    player_id: i32,
+    rated: &[RatedGame],
+) -> Result<Vec<ProfileGame>> {
+    let changes: HashMap<i32, f64> = rated
+        .iter()
+        .map(|rg| {
+            let delta = if rg.game.black == player_id {
+                rg.delta.black_delta
+            } else {
+                rg.delta.white_delta
+            };
+            (rg.id, delta)
+        })
+        .collect();
+    let mut stmt = conn.prepare(concat!(
+        "SELECT g.id, r.id, CAST(r.date AS TEXT), g.black, p.id, p.name, g.handicap, g.boardsize, g.result ",
+        "FROM games g, rounds r, players p ",
+        "WHERE g.played = r.id AND (g.white = ?1 OR g.black = ?1) ",
+        "AND p.id = CASE WHEN g.black = ?1 THEN g.white ELSE g.black END ",
+        "ORDER BY r.date, g.id"
+    ))?;
+    let games = stmt
+        .query_map([player_id], |row| {
+            let id: i32 = row.get(0)?;
+            let black: i32 = row.get(3)?;
+            let is_black = black == player_id;
+            let result: Option<GameResult> = row.get(8)?;
+            Ok(ProfileGame {
+                round_id: row.get(1)?,
+                date: row.get(2)?,
+                opponent_id: row.get(4)?,
+                opponent_name: row.get(5)?,
+                is_black,
+                handicap: Handicap::new(row.get(6)?),
+                boardsize: row.get(7)?,
+                result: result.map(|r| {
+                    if is_black {
+                        r.seen_from_black()
+                    } else {
+                        r.seen_from_white()
+                    }
+                }),
+                rating_change: changes.get(&id).map(|&d| RatingDiff(d)),
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(games)
+}
+
+/// Latest run of identical results in played games
+fn current_streak(games: &[ProfileGame]) -> Option<Streak> {
+    let mut played = games
+        .iter()
+        .rev()
+        .filter_map(|g| g.result)
+        .filter(|r| r.played_score().is_some());
+    let result = played.next()?;
+    let length = 1 + played.take_while(|&r| r == result).count();
+    Some(Streak { result, length })
+}
+
+fn profile_internal(
+    conn: &rusqlite::Connection,
+    player_id: i32,
+    today: &str,
+) -> Result<ProfileTemplate> {
+    let (player, initial_rating, default) = conn.query_row(
+        "SELECT id, name, currentrating, initialrating, defaultschedule FROM players WHERE id = ?1",
+        [player_id],
+        |row| {
+            Ok((
+                Player {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    rating: Rating::new(row.get(2)?),
+                },
+                Rating::new(row.get(3)?),
+                row.get(4)?,
+            ))
+        },
+    )?;
+    let settings = load_settings(conn)?;
+    let rated: Vec<RatedGame> = rated_games(conn, &settings)?
+        .into_iter()
+        .filter(|rg| rg.game.black == player_id || rg.game.white == player_id)
+        .collect();
+    let games = load_games(conn, player_id, &rated)?;
+    let performance = performance_ratings(&settings, &rated).remove(&player_id);
+    let mut black = Record::default();
+    let mut white = Record::default();
+    let mut handicaps: Vec<HandicapRecord> = Vec::new();
+    let mut opponents: HashMap<i32, OpponentRecord> = HashMap::new();
+    for game in &games {
+        let result = match game.result {
+            Some(result) => result,
+            None => continue,
+        };
+        if game.is_black {
+            black.add(result);
+        } else {
+            white.add(result);
+        }
+        match handicaps.iter_mut().find(|h| {
+            h.handicap.to_f64() == game.handicap.to_f64()
+                && h.boardsize == game.boardsize
+                && h.is_black == game.is_black
+        }) {
+            Some(h) => h.record.add(result),
+            None => {
+                let mut record = Record::default();
+                record.add(result);
+                handicaps.push(HandicapRecord {
+                    handicap: game.handicap,
+                    boardsize: game.boardsize,
+                    is_black: game.is_black,
+                    record,
+                });
+            }
+        }
+        let opponent = opponents
+            .entry(game.opponent_id)
+            .or_insert_with(|| OpponentRecord {
+                id: game.opponent_id,
+                name: game.opponent_name.clone(),
+                games: 0,
+                record: Record::default(),
+            });
+        opponent.games += 1;
+        opponent.record.add(result);
+    }
+    handicaps.sort_by(|a, b| {
+        a.boardsize
+            .cmp(&b.boardsize)
+            .reverse()
+            .then(a.handicap.to_f64().total_cmp(&b.handicap.to_f64()))
+            .then(b.is_black.cmp(&a.is_black))
+    });
+    let mut opponents: Vec<OpponentRecord> = opponents.into_values().collect();
+    opponents.sort_by(|a, b| b.games.cmp(&a.games).then_with(|| a.name.cmp(&b.name)));
+    opponents.truncate(FREQUENT_OPPONENTS);
+    let streak = current_streak(&games);
+    let presence = PlayerPresence {
+        default,
+        rounds: upcoming_presence(conn, player_id, today)?,
+    };
+    Ok(ProfileTemplate {
+        player,
+        initial_rating,
+        performance,
+        rank_scale: settings.rank_scale,
+        games,
+        black,
+        white,
+        handicaps,
+        opponents,
+        streak,
+        presence,
+        links: Links::Live,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    fn setup() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating, defaultschedule) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0, 1), ",
+            "(42, \"player2\", 1000.0, 1000.0, 1), ",
+            "(43, \"player3\", 1000.0, 1000.0, 0);",
+            "INSERT INTO rounds (id, \"date\") VALUES ",
+            "(97, '2019-06-03'), (98, '2019-06-10'), (99, '2019-06-17'), (100, '2019-06-24');",
+            "INSERT INTO presence (player, \"when\", schedule) VALUES (41, 100, 0);",
+            "INSERT INTO games (id, played, white, black, handicap, result) VALUES ",
+            "(30, 97, 41, 43, 2.0, 'BlackWins'), ",
+            "(31, 98, 41, 42, 0.0, 'WhiteWins'), ",
+            "(32, 98, 43, 41, 0.0, 'WhiteWins'), ",
+            "(33, 99, 42, 41, 0.0, 'BlackWinsByDefault'), ",
+            "(34, 99, 41, 43, 0.0, 'Jigo'), ",
+            "(35, 100, 41, 42, 0.0, NULL);"
+        ))
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn profile_1() {
+        let conn = setup();
+        let pt = profile_internal(&conn, 41, "2019-06-20").unwrap();
+        assert_eq!(pt.player.name, "player1");
+        assert_eq!(pt.games.len(), 6);
+        let ids: Vec<_> = pt.games.iter().map(|g| g.opponent_id).collect();
+        assert_eq!(ids, [43, 42, 43, 42, 43, 42]);
+        assert_eq!(pt.games[0].colour(), "white");
+        assert_eq!(pt.games[0].description(), "loss");
+        assert_eq!(pt.games[2].colour(), "black");
+        assert_eq!(pt.games[3].description(), "win by default");
+        assert_eq!(pt.games[5].description(), "not played yet");
+        // Rated games have a rating change, forfeits and unplayed games none.
+        assert!(pt.games[0].rating_change.unwrap().0 < 0.0);
+        assert!(pt.games[1].rating_change.unwrap().0 > 0.0);
+        assert!(pt.games[3].rating_change.is_none());
+        assert!(pt.games[5].rating_change.is_none());
+        // One win, two losses and a jigo against equally rated players
+        assert!(pt.performance.unwrap().0 < 1000.0);
+        assert_eq!(pt.white.to_string(), "1–1–1");
+        assert_eq!(pt.black.to_string(), "0–1–0");
+        let handicaps: Vec<_> = pt
+            .handicaps
+            .iter()
+            .map(|h| (h.handicap.to_f64(), h.colour(), h.record.to_string()))
+            .collect();
+        assert_eq!(
+            handicaps,
+            [
+                (0.0, "black", "0–1–0".to_owned()),
+                (0.0, "white", "1–0–1".to_owned()),
+                (2.0, "white", "0–1–0".to_owned())
+            ]
+        );
+        let opponents: Vec<_> = pt.opponents.iter().map(|o| (o.id, o.games)).collect();
+        assert_eq!(opponents, [(43, 3), (42, 2)]);
+        assert_eq!(
+            pt.streak,
+            Some(Streak {
+                result: OneSidedGameResult::Jigo,
+                length: 1
+            })
+        );
+        assert!(pt.presence.default);
+        assert_eq!(pt.presence.rounds.len(), 1);
+        assert!(pt.presence.rounds[0].is_absent());
+    }
+
+    #[test]
+    fn profile_handicaps() {
+        let conn = setup();
+        // Player 1 gave two stones in game 30 and receives them here.
+        conn.execute_batch(concat!(
+            "INSERT INTO games (id, played, white, black, handicap, result) VALUES ",
+            "(36, 100, 42, 41, 2.0, 'BlackWins');"
+        ))
+        .unwrap();
+        let pt = profile_internal(&conn, 41, "2019-06-20").unwrap();
+        let handicaps: Vec<_> = pt
+            .handicaps
+            .iter()
+            .filter(|h| h.handicap.to_f64() == 2.0)
+            .map(|h| (h.colour(), h.record.to_string()))
+            .collect();
+        assert_eq!(
+            handicaps,
+            [("black", "1–0–0".to_owned()), ("white", "0–1–0".to_owned())]
+        );
+    }
+
+    #[test]
+    fn streak() {
+        let conn = setup();
+        let pt = profile_internal(&conn, 43, "2019-06-20").unwrap();
+        assert_eq!(pt.streak.unwrap().to_string(), "1 jigo");
+        conn.execute_batch("UPDATE games SET result = 'BlackWins' WHERE id = 34")
+            .unwrap();
+        let pt = profile_internal(&conn, 43, "2019-06-20").unwrap();
+        assert_eq!(pt.streak.unwrap().to_string(), "3 wins");
+        // The forfeit does not count towards the streak.
+        let pt = profile_internal(&conn, 42, "2019-06-20").unwrap();
+        assert_eq!(pt.streak.unwrap().to_string(), "1 loss");
+        assert!(!pt.presence.rounds[0].is_absent());
+        assert!(profile_internal(&conn, 49, "2019-06-20").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Season settings page
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use actix_web::Responder;
+use askama::Template;
+
+use gorating::RankScale;
+
+use crate::db::{load_settings, save_settings};
+use crate::models::{RatingFormula, RatingPeriod, Settings};
+use crate::update_ratings::update_ratings;
+use crate::{parse_initial_rating, CommonTemplate, Error, Result};
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct SettingsTemplate {
+    settings: Settings,
+}
+impl CommonTemplate for SettingsTemplate {}
+
+impl SettingsTemplate {
+    fn max_handicap(&self) -> String {
+        self.settings
+            .max_handicap
+            .map(|x| x.to_string())
+            .unwrap_or_default()
+    }
+
+    fn max_drop(&self) -> String {
+        self.settings
+            .max_drop
+            .map(|x| x.to_string())
+            .unwrap_or_default()
+    }
+
+    fn no_handicap_above(&self) -> String {
+        self.settings
+            .no_handicap_above
+            .map(|x| x.to_string())
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) fn settings(conn: &rusqlite::Connection) -> Result<impl Responder> {
+    Ok(SettingsTemplate {
+        settings: load_settings(conn)?,
+    })
+}
+
+fn parse_param<T: FromStr>(params: &HashMap<String, String>, name: &'static str) -> Result<T> {
+    params
+        .get(name)
+        .and_then(|s| T::from_str(s.trim()).ok())
+        .ok_or(Error::BadParam(name))
+}
+
+/// Parse a parameter that may be left empty
+fn parse_optional_param<T: FromStr>(
+    params: &HashMap<String, String>,
+    name: &'static str,
+) -> Result<Option<T>> {
+    match params.get(name).map(|s| s.trim()) {
+        Some("") => Ok(None),
+        _ => parse_param(params, name).map(Some),
+    }
+}
+
+/// Parse a parameter that must be a finite number
+fn parse_finite_param(params: &HashMap<String, String>, name: &'static str) -> Result<f64> {
+    let x: f64 = parse_param(params, name)?;
+    if x.is_finite() {
+        Ok(x)
+    } else {
+        Err(Error::BadParam(name))
+    }
+}
+
+fn parse_settings(settings: &mut Settings, params: &HashMap<String, String>) -> Result<()> {
+    settings.rank_scale = parse_param(params, "rank_scale")?;
+    settings.rating_formula = parse_param(params, "rating_formula")?;
+    settings.bonus_factor = parse_finite_param(params, "bonus_factor")?;
+    if settings.bonus_factor < 0.0 {
+        return Err(Error::BadParam("bonus_factor"));
+    }
+    settings.min_rating = parse_finite_param(params, "min_rating")?;
+    settings.max_drop = parse_optional_param(params, "max_drop")?;
+    if settings.max_drop.is_some_and(|x| !x.is_finite() || x < 0.0) {
+        return Err(Error::BadParam("max_drop"));
+    }
+    settings.max_drop_period = parse_param(params, "max_drop_period")?;
+    settings.provisional_games = parse_param(params, "provisional_games")?;
+    settings.provisional_factor = parse_param(params, "provisional_factor")?;
+    if !settings.provisional_factor.is_finite() || settings.provisional_factor <= 0.0 {
+        return Err(Error::BadParam("provisional_factor"));
+    }
+    settings.handicap_reduction = parse_param(params, "handicap_reduction")?;
+    if !settings.handicap_reduction.is_finite() || settings.handicap_reduction < 0.0 {
+        return Err(Error::BadParam("handicap_reduction"));
+    }
+    settings.max_handicap = parse_optional_param(params, "max_handicap")?;
+    settings.no_handicap_above = match params.get("no_handicap_above").map(|s| s.trim()) {
+        Some("") => None,
+        Some(s) => Some(
+            parse_initial_rating(s, settings).map_err(|_| Error::BadParam("no_handicap_above"))?,
+        ),
+        None => return Err(Error::BadParam("no_handicap_above")),
+    };
+    settings.inactive_after_rounds = parse_param(params, "inactive_after_rounds")?;
+    settings.hide_inactive = params.get("hide_inactive").is_some();
+    settings.skip_inactive_schedule = params.get("skip_inactive_schedule").is_some();
+    settings.award_min_games = parse_param(params, "award_min_games")?;
+    Ok(())
+}
+
+/// Save changed settings and recalculate all ratings accordingly
+pub(crate) fn settings_save(
+    conn: &mut rusqlite::Connection,
+    params: &HashMap<String, String>,
+) -> Result<()> {
+    let trans = conn.transaction()?;
+    let mut settings = load_settings(&trans)?;
+    parse_settings(&mut settings, params)?;
+    save_settings(&trans, &settings)?;
+    update_ratings(&trans)?;
+    trans.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    fn params(list: &[(&str, &str)]) -> HashMap<String, String> {
+        list.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    fn valid_params() -> HashMap<String, String> {
+        params(&[
+            ("provisional_games", "5"),
+            ("provisional_factor", "2.5"),
+            ("handicap_reduction", "0"),
+            ("max_handicap", ""),
+            ("no_handicap_above", ""),
+            ("inactive_after_rounds", "0"),
+            ("award_min_games", "5"),
+            ("rank_scale", "egd"),
+            ("rating_formula", "egd2021"),
+            ("bonus_factor", "0.2"),
+            ("min_rating", "-900"),
+            ("max_drop", "100"),
+            ("max_drop_period", "round"),
+        ])
+    }
+
+    #[test]
+    fn settings_save_1() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        settings_save(&mut conn, &valid_params()).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.provisional_games, 5);
+        assert_eq!(settings.provisional_factor, 2.5);
+        assert_eq!(settings.max_handicap, None);
+        assert_eq!(settings.no_handicap_above, None);
+    }
+
+    #[test]
+    fn settings_save_handicap_policy() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let mut p = valid_params();
+        p.insert("handicap_reduction".to_owned(), "1".to_owned());
+        p.insert("max_handicap".to_owned(), "9".to_owned());
+        p.insert("no_handicap_above".to_owned(), "3d".to_owned());
+        settings_save(&mut conn, &p).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.handicap_reduction, 1.0);
+        assert_eq!(settings.max_handicap, Some(9));
+        assert_eq!(settings.no_handicap_above, Some(2300.0));
+        let st = SettingsTemplate { settings };
+        assert_eq!(st.max_handicap(), "9");
+        assert_eq!(st.no_handicap_above(), "2300");
+    }
+
+    #[test]
+    fn settings_save_rank_scale() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let mut p = valid_params();
+        p.insert("rank_scale".to_owned(), "aga".to_owned());
+        p.insert("no_handicap_above".to_owned(), "3d".to_owned());
+        settings_save(&mut conn, &p).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.rank_scale, RankScale::Aga);
+        // The rank is interpreted on the new scale.
+        assert_eq!(settings.no_handicap_above, Some(2200.0));
+    }
+
+    #[test]
+    fn settings_save_rating_system() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 150.0, 150.0), ",
+            "(42, \"player2\", 150.0, 150.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(33, 99, 41, 42, 'WhiteWins');"
+        ))
+        .unwrap();
+        let mut p = valid_params();
+        p.insert("rating_formula".to_owned(), "egd_legacy".to_owned());
+        p.insert("bonus_factor".to_owned(), "0".to_owned());
+        p.insert("min_rating".to_owned(), "100".to_owned());
+        p.insert("max_drop".to_owned(), "".to_owned());
+        p.insert("max_drop_period".to_owned(), "season".to_owned());
+        settings_save(&mut conn, &p).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.rating_formula, RatingFormula::EgdLegacy);
+        assert_eq!(settings.min_rating, 100.0);
+        assert_eq!(settings.max_drop, None);
+        assert_eq!(settings.max_drop_period, RatingPeriod::Season);
+        // Ratings are recalculated with the new rating system.
+        let loser: f64 = conn
+            .query_row(
+                "SELECT currentrating FROM players WHERE id = 42",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(loser, 100.0);
+    }
+
+    #[test]
+    fn settings_save_inactivity() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let mut p = valid_params();
+        p.insert("inactive_after_rounds".to_owned(), "4".to_owned());
+        p.insert("hide_inactive".to_owned(), "on".to_owned());
+        settings_save(&mut conn, &p).unwrap();
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.inactive_after_rounds, 4);
+        assert!(settings.hide_inactive);
+        assert!(!settings.skip_inactive_schedule);
+    }
+
+    #[test]
+    fn settings_save_awards() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        assert_eq!(load_settings(&conn).unwrap().award_min_games, 5);
+        let mut p = valid_params();
+        p.insert("award_min_games".to_owned(), "10".to_owned());
+        settings_save(&mut conn, &p).unwrap();
+        assert_eq!(load_settings(&conn).unwrap().award_min_games, 10);
+    }
+
+    #[test]
+    fn settings_save_errors() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        assert!(settings_save(&mut conn, &params(&[])).is_err());
+        for &(name, value) in &[
+            ("provisional_games", "-1"),
+            ("provisional_factor", "0"),
+            ("provisional_factor", "NaN"),
+            ("handicap_reduction", "-1"),
+            ("handicap_reduction", ""),
+            ("max_handicap", "-1"),
+            ("max_handicap", "many"),
+            ("no_handicap_above", "strong"),
+            ("inactive_after_rounds", ""),
+            ("award_min_games", "-1"),
+            ("rank_scale", "igs"),
+            ("rating_formula", "elo"),
+            ("bonus_factor", "-0.1"),
+            ("min_rating", "inf"),
+            ("max_drop", "-5"),
+            ("max_drop_period", "week"),
+        ] {
+            let mut p = valid_params();
+            p.insert(name.to_owned(), value.to_owned());
+            assert!(settings_save(&mut conn, &p).is_err());
+        }
+        let settings = load_settings(&conn).unwrap();
+        assert_eq!(settings.provisional_games, 0);
+    }
+}
@@ -1,12 +1,20 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 
-use actix_web::Responder;
+use actix_web::{http, Either, HttpResponse, Responder};
 use askama::Template;
+use serde::Serialize;
 
-use gorating::Rating;
+use gorating::{Handicap, Rank, RankScale, Rating};
 
-use crate::models::{Colour, GameResult, OneSidedGame, Round, RoundExtra, StandingsPlayer};
-use crate::{get_today, CommonTemplate, Result};
+use crate::db::load_settings;
+use crate::inactivity::inactive_players;
+use crate::models::{
+    Colour, GameResult, Links, OneSidedGame, Round, RoundExtra, RoundsByMonth, StandingsOrder,
+    StandingsPlayer,
+};
+use crate::update_ratings::{ratings_as_of, stored_performance_ratings};
+use crate::{get_today, CommonTemplate, Error, Result};
 
 #[derive(Template)]
 #[template(path = "standings.html")]
@@ -14,20 +22,433 @@ struct StandingsTemplate {
     today: String,
     rounds: Vec<Round>,
     players: Vec<StandingsPlayer>,
+    hide_inactive: bool,
+    rank_scale: RankScale,
+    options: StandingsOptions,
+    /// All rounds of the season, for choosing the standings of a month
+    months: RoundsByMonth,
+    /// Performance ratings in the games of the standings
+    performances: HashMap<i32, Rating>,
     games: i64,
     white_wins: i64,
     black_wins: i64,
     jigo: i64,
     forfeit: i64,
+    links: Links,
+}
+impl CommonTemplate for StandingsTemplate {
+    fn links(&self) -> Links {
+        self.links
+    }
+}
+
+impl StandingsTemplate {
+    fn performance(&self, player_id: &i32) -> Option<Rating> {
+        self.performances.get(player_id).copied()
+    }
+}
+
+/// How the players in the standings are ordered
+#[derive(Debug)]
+struct StandingsOptions {
+    order: StandingsOrder,
+    /// Minimum number of games to be ordered by win percentage
+    min_games: u32,
+    /// Show the standings after the rounds up to and including this date
+    /// instead of the present
+    as_of: Option<String>,
+    /// Count only the games from this date on
+    from: Option<String>,
+}
+
+impl Default for StandingsOptions {
+    fn default() -> Self {
+        StandingsOptions {
+            order: StandingsOrder::Rating,
+            min_games: 1,
+            as_of: None,
+            from: None,
+        }
+    }
+}
+
+impl StandingsOptions {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self> {
+        let mut options = StandingsOptions::default();
+        if let Some(order) = params.get("order") {
+            options.order = order.parse().map_err(|_| Error::BadParam("order"))?;
+        }
+        match params.get("min_games").map(|s| s.trim()) {
+            None | Some("") => (),
+            Some(s) => options.min_games = s.parse().map_err(|_| Error::BadParam("min_games"))?,
+        }
+        options.as_of = date_param(params, "as_of")?;
+        options.from = date_param(params, "from")?;
+        match params.get("month").map(|s| s.trim()) {
+            None | Some("") => (),
+            Some(_) if options.as_of.is_some() || options.from.is_some() => {
+                return Err(Error::BadParam("month"))
+            }
+            Some(s) => {
+                let (from, until) = month_range(s).ok_or(Error::BadParam("month"))?;
+                options.from = Some(from);
+                options.as_of = Some(until);
+            }
+        }
+        if let (Some(from), Some(as_of)) = (&options.from, &options.as_of) {
+            if from > as_of {
+                return Err(Error::BadParam("from"));
+            }
+        }
+        Ok(options)
+    }
+
+    /// Query string that shows the standings with these options again
+    fn query_string(&self) -> String {
+        let mut s = format!("order={}&min_games={}", self.order.to_str(), self.min_games);
+        if let Some(from) = &self.from {
+            s += &format!("&from={}", from);
+        }
+        if let Some(as_of) = &self.as_of {
+            s += &format!("&as_of={}", as_of);
+        }
+        s
+    }
+}
+
+fn is_date(s: &str) -> bool {
+    s.len() == 10 && time::strptime(s, "%Y-%m-%d").is_ok()
 }
-impl CommonTemplate for StandingsTemplate {}
 
-pub(crate) fn standings(conn: &rusqlite::Connection) -> Result<impl Responder> {
-    let today = get_today();
-    standings_internal(conn, today)
+/// Optional date parameter in the format YYYY-MM-DD
+fn date_param(params: &HashMap<String, String>, name: &'static str) -> Result<Option<String>> {
+    match params.get(name).map(|s| s.trim()) {
+        None | Some("") => Ok(None),
+        Some(s) if is_date(s) => Ok(Some(s.to_owned())),
+        Some(_) => Err(Error::BadParam(name)),
+    }
+}
+
+/// The day before a valid date in the format YYYY-MM-DD
+fn previous_day(date: &str) -> String {
+    let tm = time::strptime(date, "%Y-%m-%d").unwrap();
+    time::at_utc(tm.to_timespec() - time::Duration::days(1))
+        .strftime("%Y-%m-%d")
+        .unwrap()
+        .to_string()
 }
 
-fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<StandingsTemplate> {
+/// First and last day of a month in the format YYYY-MM
+fn month_range(month: &str) -> Option<(String, String)> {
+    let first = format!("{}-01", month);
+    if month.len() != 7 || !is_date(&first) {
+        return None;
+    }
+    let year: i32 = month[..4].parse().ok()?;
+    let month: u32 = month[5..].parse().ok()?;
+    let next = if month == 12 {
+        format!("{:04}-01-01", year + 1)
+    } else {
+        format!("{:04}-{:02}-01", year, month + 1)
+    };
+    Some((first, previous_day(&next)))
+}
+
+pub(crate) fn standings(
+    conn: &rusqlite::Connection,
+    params: &HashMap<String, String>,
+) -> Result<impl Responder> {
+    let options = StandingsOptions::from_params(params)?;
+    let today = options.as_of.clone().unwrap_or_else(get_today);
+    let format = params.get("format").map(|s| s.as_str());
+    if !matches!(format, None | Some("html" | "csv" | "json")) {
+        return Err(Error::BadParam("format"));
+    }
+    let st = standings_internal(conn, today, options)?;
+    Ok(match format {
+        Some("csv") => Either::Right(attachment(
+            "text/csv; charset=utf-8",
+            format!("standings_{}.csv", st.today),
+            standings_csv(&st),
+        )),
+        Some("json") => Either::Right(attachment(
+            "application/json",
+            format!("standings_{}.json", st.today),
+            serde_json::to_string_pretty(&standings_export(&st))?,
+        )),
+        _ => Either::Left(st),
+    })
+}
+
+fn attachment(content_type: &'static str, filename: String, body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .append_header((
+            http::header::CONTENT_DISPOSITION,
+            http::header::ContentDisposition {
+                disposition: http::header::DispositionType::Attachment,
+                parameters: vec![http::header::DispositionParam::Filename(filename)],
+            },
+        ))
+        .body(body)
+}
+
+#[derive(Serialize)]
+struct ExportGame {
+    /// Place of the opponent, or 0 if the opponent is not in the standings
+    opponent_place: usize,
+    colour: &'static str,
+    /// Handicap as stored, in its human form such as "3b5"
+    handicap: Handicap,
+    /// Handicap used for the rating calculation, rounded to half stones
+    effective_handicap: f64,
+    result: &'static str,
+}
+
+#[derive(Serialize)]
+struct ExportPlayer<'a> {
+    place: usize,
+    place_change: isize,
+    name: &'a str,
+    inactive: bool,
+    default_schedule: bool,
+    rating: Rating,
+    rating_change: f64,
+    provisional: bool,
+    rank: Rank,
+    score: f64,
+    games: i64,
+    win_percentage: Option<f64>,
+    performance: Option<Rating>,
+    sos: f64,
+    sodos: f64,
+    /// Games in every round of the standings
+    results: Vec<Vec<ExportGame>>,
+}
+
+#[derive(Serialize)]
+struct ExportRound<'a> {
+    id: i32,
+    date: &'a str,
+}
+
+#[derive(Serialize)]
+struct ExportStandings<'a> {
+    program_version: &'static str,
+    date: &'a str,
+    from: Option<&'a str>,
+    rounds: Vec<ExportRound<'a>>,
+    players: Vec<ExportPlayer<'a>>,
+}
+
+/// The standings as shown on the page, for serializing to JSON
+fn standings_export(st: &StandingsTemplate) -> ExportStandings<'_> {
+    let players = st
+        .players
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| !(p.inactive && st.hide_inactive))
+        .map(|(idx, p)| ExportPlayer {
+            place: idx + 1,
+            place_change: p.place_diff(idx + 1).0,
+            name: &p.name,
+            inactive: p.inactive,
+            default_schedule: p.default_schedule,
+            rating: p.currentrating,
+            rating_change: p.rating_diff().0,
+            provisional: p.provisional,
+            rank: st.rank_scale.rank(p.currentrating),
+            score: p.score,
+            games: p.games,
+            win_percentage: p.win_percentage().map(|wp| wp.0),
+            performance: st.performance(&p.id),
+            sos: p.sos,
+            sodos: p.sodos,
+            results: p
+                .results
+                .iter()
+                .map(|games| {
+                    games
+                        .iter()
+                        .map(|g| ExportGame {
+                            opponent_place: g.other_place,
+                            colour: g.colour.name(),
+                            handicap: g.stored_handicap,
+                            effective_handicap: g.handicap,
+                            result: g.result.description(),
+                        })
+                        .collect()
+                })
+                .collect(),
+        })
+        .collect();
+    ExportStandings {
+        program_version: env!("CARGO_PKG_VERSION"),
+        date: &st.today,
+        from: st.options.from.as_deref(),
+        rounds: st
+            .rounds
+            .iter()
+            .map(|r| ExportRound {
+                id: r.id,
+                date: &r.date,
+            })
+            .collect(),
+        players,
+    }
+}
+
+/// Quote a CSV field if needed
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// The standings as shown on the page, with a column for every round
+fn standings_csv(st: &StandingsTemplate) -> String {
+    let mut header: Vec<String> = [
+        "Place",
+        "Place change",
+        "Name",
+        "Default schedule",
+        "Rating",
+        "Rating change",
+        "Rank",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    header.extend(st.rounds.iter().map(|r| r.date.clone()));
+    header.extend(
+        ["Score", "Games", "Win %", "Performance", "SOS", "SODOS"]
+            .iter()
+            .map(|s| s.to_string()),
+    );
+    let mut lines = vec![header];
+    for (idx, p) in st.players.iter().enumerate() {
+        if p.inactive && st.hide_inactive {
+            continue;
+        }
+        let mut line = vec![
+            (idx + 1).to_string(),
+            p.place_diff(idx + 1).0.to_string(),
+            p.name.clone(),
+            if p.default_schedule { "yes" } else { "no" }.to_owned(),
+            p.currentrating.to_string(),
+            p.rating_diff().0.round().to_string(),
+            st.rank_scale.rank(p.currentrating).to_string(),
+        ];
+        line.extend(p.results.iter().map(|games| {
+            games
+                .iter()
+                .map(|g| g.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }));
+        line.extend(vec![
+            p.score.to_string(),
+            p.games.to_string(),
+            p.win_percentage()
+                .map_or(String::new(), |wp| wp.to_string()),
+            st.performance(&p.id)
+                .map_or(String::new(), |r| r.to_string()),
+            p.sos.to_string(),
+            p.sodos.to_string(),
+        ]);
+        lines.push(line);
+    }
+    lines
+        .iter()
+        .map(|line| {
+            let fields: Vec<String> = line.iter().map(|f| csv_field(f)).collect();
+            fields.join(",") + "\r\n"
+        })
+        .collect()
+}
+
+/// Render the present standings for the static website
+pub(crate) fn render_static(conn: &rusqlite::Connection, today: &str) -> Result<String> {
+    let mut st = standings_internal(conn, today.to_owned(), StandingsOptions::default())?;
+    st.links = Links::Static { root: "" };
+    Ok(st.render()?)
+}
+
+/// Compare numbers so that the highest comes first
+fn descending(a: f64, b: f64) -> Ordering {
+    b.partial_cmp(&a).unwrap_or(Ordering::Equal)
+}
+
+/// Order the players, keeping inactive players after all active players and
+/// the order by rating for players that are otherwise equal. `played` lists
+/// the opponents and points of every player in played games.
+fn sort_players(
+    players: &mut [StandingsPlayer],
+    options: &StandingsOptions,
+    played: &HashMap<i32, Vec<(i32, f64)>>,
+) {
+    let min_games = i64::from(options.min_games);
+    let qualified = |p: &StandingsPlayer| p.games >= min_games && p.games > 0;
+    players.sort_by(|a, b| {
+        a.inactive
+            .cmp(&b.inactive)
+            .then_with(|| match options.order {
+                StandingsOrder::Rating => Ordering::Equal,
+                StandingsOrder::Score => descending(a.score, b.score),
+                StandingsOrder::WinPercentage => match (qualified(a), qualified(b)) {
+                    (true, true) => descending(a.score / a.games as f64, b.score / b.games as f64),
+                    (qualified_a, qualified_b) => qualified_b.cmp(&qualified_a),
+                },
+                StandingsOrder::Points => descending(a.score, b.score)
+                    .then_with(|| descending(a.sos, b.sos))
+                    .then_with(|| descending(a.sodos, b.sodos)),
+            })
+    });
+    if options.order != StandingsOrder::Points {
+        return;
+    }
+    // Players who are still tied are ordered by the results of the games
+    // among themselves.
+    let tied = |a: &StandingsPlayer, b: &StandingsPlayer| {
+        (a.inactive, a.score, a.sos, a.sodos) == (b.inactive, b.score, b.sos, b.sodos)
+    };
+    let mut start = 0;
+    while start < players.len() {
+        let mut end = start + 1;
+        while end < players.len() && tied(&players[start], &players[end]) {
+            end += 1;
+        }
+        if end - start > 1 {
+            let group = &mut players[start..end];
+            let ids: Vec<i32> = group.iter().map(|p| p.id).collect();
+            let head_to_head: HashMap<i32, f64> = ids
+                .iter()
+                .map(|id| {
+                    let points = played
+                        .get(id)
+                        .into_iter()
+                        .flatten()
+                        .filter(|(opponent, _)| ids.contains(opponent))
+                        .map(|(_, points)| points)
+                        .sum();
+                    (*id, points)
+                })
+                .collect();
+            group.sort_by(|a, b| descending(head_to_head[&a.id], head_to_head[&b.id]));
+        }
+        start = end;
+    }
+}
+
+fn standings_internal(
+    conn: &rusqlite::Connection,
+    today: String,
+    options: StandingsOptions,
+) -> Result<StandingsTemplate> {
+    let settings = load_settings(conn)?;
+    let inactive = inactive_players(conn, &settings, &today)?;
     let mut stmt = conn.prepare("SELECT id FROM players ORDER BY initialrating DESC, id")?;
     let original_indices: HashMap<i32, usize> = stmt
         .query_map([], |row| {
@@ -42,13 +463,18 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
             concat!("SELECT p.id, p.name, p.defaultschedule, p.initialrating, p.currentrating, ",
             "COUNT(g.id), ",
             "COUNT((p.id = g.black AND g.result IN ('BlackWins', 'BlackWinsByDefault')) OR (p.id = g.white AND g.result IN ('WhiteWins', 'WhiteWinsByDefault')) OR NULL), ",
-            "COUNT(g.result = 'Jigo' OR NULL) ",
+            "COUNT(g.result = 'Jigo' OR NULL), ",
+            "(SELECT COUNT(*) FROM games g2, rounds r2 WHERE g2.played = r2.id AND (p.id = g2.black OR p.id = g2.white) ",
+            "AND g2.result IN ('WhiteWins', 'BlackWins', 'Jigo') AND (?1 IS NULL OR r2.date <= ?1)) ",
             "FROM players p ",
             "LEFT OUTER JOIN games g ON (p.id = g.black OR p.id = g.white) AND g.result IS NOT NULL ",
+            "AND g.played IN (SELECT id FROM rounds WHERE (?1 IS NULL OR date <= ?1) AND (?2 IS NULL OR date >= ?2)) ",
             "GROUP BY p.id ORDER BY p.currentrating DESC, p.id"),
         )?;
+    let as_of = options.as_of.as_deref();
+    let from = options.from.as_deref();
     let mut players: Vec<StandingsPlayer> = stmt
-        .query_map([], |row| {
+        .query_map([as_of, from], |row| {
             let id: i32 = row.get(0)?;
             let name: String = row.get(1)?;
             let default_schedule: bool = row.get(2)?;
@@ -57,6 +483,7 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
             let games: i64 = row.get(5)?;
             let wins: i64 = row.get(6)?;
             let jigos: i64 = row.get(7)?;
+            let rated_games: u32 = row.get(8)?;
             let score = wins as f64 + 0.5 * jigos as f64;
             Ok(StandingsPlayer {
                 id,
@@ -65,14 +492,47 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
                 default_schedule,
                 initialrating,
                 currentrating,
+                provisional: rated_games < settings.provisional_games,
+                inactive: inactive.contains(&id),
                 results: Vec::new(),
                 score,
                 games,
+                sos: 0.0,
+                sodos: 0.0,
             })
         })?
         .collect::<rusqlite::Result<_>>()?;
+    if let Some(date) = as_of {
+        let ratings = ratings_as_of(conn, &settings, date)?;
+        for player in players.iter_mut() {
+            player.currentrating = ratings[&player.id];
+        }
+        players.sort_by(|a, b| {
+            descending(a.currentrating.0, b.currentrating.0).then_with(|| a.id.cmp(&b.id))
+        });
+    }
+    if let Some(from) = from {
+        // The rating change and the change of place are counted from the
+        // start of the date range instead of the start of the season.
+        let start = ratings_as_of(conn, &settings, &previous_day(from))?;
+        let mut ids: Vec<i32> = players.iter().map(|p| p.id).collect();
+        ids.sort_by(|a, b| descending(start[a].0, start[b].0).then_with(|| a.cmp(b)));
+        let start_places: HashMap<i32, usize> = ids
+            .into_iter()
+            .enumerate()
+            .map(|(idx, id)| (id, idx + 1))
+            .collect();
+        for player in players.iter_mut() {
+            player.initialrating = start[&player.id];
+            player.original_index = start_places[&player.id];
+        }
+    }
+    // Inactive players are placed after all active players.
+    players.sort_by_key(|p| p.inactive);
     let mut rounds = Vec::<Round>::new();
     let (mut games, mut white_wins, mut black_wins, mut jigo, mut forfeit) = (0, 0, 0, 0, 0);
+    let mut played: HashMap<i32, Vec<(i32, f64)>> = HashMap::new();
+    let mut game_ids = HashSet::new();
     {
         let mut players_by_id: HashMap<i32, (usize, &mut StandingsPlayer)> = players
             .iter_mut()
@@ -83,16 +543,20 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
             "SELECT r.id, r.date, r.extra, g.id, g.white, g.black, g.handicap, g.result ",
             "FROM rounds r, games g ",
             "WHERE g.played = r.id AND g.result IS NOT NULL ",
+            "AND (?1 IS NULL OR r.date <= ?1) AND (?2 IS NULL OR r.date >= ?2) ",
             "ORDER BY r.date, g.id"
         ))?;
-        stmt.query_map([], |row| {
+        stmt.query_map([as_of, from], |row| {
             let round_id: i32 = row.get(0)?;
             let round_date: String = row.get(1)?;
             let round_extra: RoundExtra = row.get(2)?;
             let game_id: i32 = row.get(3)?;
+            game_ids.insert(game_id);
             let white_id: i32 = row.get(4)?;
             let black_id: i32 = row.get(5)?;
-            let handicap: f64 = row.get(6)?;
+            let stored_handicap = Handicap::new(row.get(6)?);
+            // Rounded to half stones to keep the table compact
+            let handicap = (stored_handicap.effective() * 2.0).round() / 2.0;
             let result: GameResult = row.get(7)?;
             if rounds.last().map(|r| r.id) != Some(round_id) {
                 rounds.push(Round {
@@ -109,6 +573,7 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
                     colour: Colour::Black,
                     other_place: white_place,
                     handicap: handicap,
+                    stored_handicap,
                     result: result.seen_from_black(),
                 };
                 while black.results.len() < rounds.len() {
@@ -122,6 +587,7 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
                     colour: Colour::White,
                     other_place: black_place,
                     handicap: handicap,
+                    stored_handicap,
                     result: result.seen_from_white(),
                 };
                 while white.results.len() < rounds.len() {
@@ -129,6 +595,13 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
                 }
                 white.results.last_mut().unwrap().push(osg);
             }
+            if let Some(points) = result.seen_from_black().played_score() {
+                played.entry(black_id).or_default().push((white_id, points));
+                played
+                    .entry(white_id)
+                    .or_default()
+                    .push((black_id, 1.0 - points));
+            }
             games += 1;
             match result {
                 GameResult::WhiteWins => white_wins += 1,
@@ -147,15 +620,58 @@ fn standings_internal(conn: &rusqlite::Connection, today: String) -> Result<Stan
             player.results.push(Vec::new());
         }
     }
+    let scores: HashMap<i32, f64> = players.iter().map(|p| (p.id, p.score)).collect();
+    for player in players.iter_mut() {
+        for &(opponent, points) in played.get(&player.id).into_iter().flatten() {
+            let opponent_score = scores.get(&opponent).copied().unwrap_or(0.0);
+            player.sos += opponent_score;
+            player.sodos += points * opponent_score;
+        }
+    }
+    if options.order != StandingsOrder::Rating {
+        // The places of the opponents were taken from the order by rating.
+        let old_order: Vec<i32> = players.iter().map(|p| p.id).collect();
+        sort_players(&mut players, &options, &played);
+        let places: HashMap<i32, usize> = players
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| (p.id, idx + 1))
+            .collect();
+        for player in players.iter_mut() {
+            for osg in player.results.iter_mut().flatten() {
+                if let Some(id) = osg.other_place.checked_sub(1).map(|idx| old_order[idx]) {
+                    osg.other_place = places[&id];
+                }
+            }
+        }
+    }
+    let performances = stored_performance_ratings(conn, &settings, &game_ids)?;
+    let mut stmt = conn.prepare("SELECT id, date, extra FROM rounds ORDER BY date")?;
+    let months = RoundsByMonth(
+        stmt.query_map([], |row| {
+            Ok(Round {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                extra: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?,
+    );
     Ok(StandingsTemplate {
         today,
         rounds,
         players,
+        hide_inactive: settings.hide_inactive,
+        rank_scale: settings.rank_scale,
+        options,
+        months,
+        performances,
         games,
         white_wins,
         black_wins,
         jigo,
         forfeit,
+        links: Links::Live,
     })
 }
 
@@ -169,7 +685,8 @@ mod tests {
     fn calc_standings_0() {
         let conn = rusqlite::Connection::open_in_memory().unwrap();
         ensure_schema(&conn).unwrap();
-        let st = standings_internal(&conn, "2019-06-18".into()).unwrap();
+        let st =
+            standings_internal(&conn, "2019-06-18".into(), StandingsOptions::default()).unwrap();
         assert_eq!(st.today, "2019-06-18");
         assert!(st.rounds.is_empty());
         assert!(st.players.is_empty());
@@ -216,7 +733,8 @@ mod tests {
                 .unwrap();
             trans.commit().unwrap();
         }
-        let st = standings_internal(&conn, "2019-06-18".into()).unwrap();
+        let st =
+            standings_internal(&conn, "2019-06-18".into(), StandingsOptions::default()).unwrap();
         assert_eq!(st.today, "2019-06-18");
         assert_eq!(st.rounds.len(), 1);
         let round = &st.rounds[0];
@@ -238,6 +756,7 @@ mod tests {
             assert_eq!(p1r1r.result, OneSidedGameResult::Win);
             assert_eq!(p1.score, 1.0);
             assert_eq!(p1.games, 1);
+            assert!(!p1.provisional);
         }
         {
             let p2 = &st.players[1];
@@ -261,4 +780,337 @@ mod tests {
         assert_eq!(st.jigo, 0);
         assert_eq!(st.forfeit, 0);
     }
+
+    #[test]
+    fn calc_standings_provisional() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1020.0), ",
+            "(42, \"player2\", 1000.0, 980.0), ",
+            "(43, \"player3\", 1000.0, 1000.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(33, 99, 41, 42, 'WhiteWins'), ",
+            "(34, 99, 43, 42, 'WhiteWinsByDefault');",
+            "INSERT INTO settings (id, extra) VALUES (1, '{\"provisional_games\": 1}');"
+        ))
+        .unwrap();
+        let st =
+            standings_internal(&conn, "2019-06-18".into(), StandingsOptions::default()).unwrap();
+        let provisional: Vec<_> = st.players.iter().map(|p| (p.id, p.provisional)).collect();
+        assert_eq!(provisional, [(41, false), (43, true), (42, false)]);
+    }
+
+    #[test]
+    fn calc_standings_inactive() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1020.0), ",
+            "(42, \"player2\", 1000.0, 980.0), ",
+            "(43, \"player3\", 1000.0, 1100.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (98, '2019-06-10'), (99, '2019-06-17');",
+            "INSERT INTO presence (player, \"when\", schedule) VALUES (43, 98, 0);",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(33, 99, 41, 42, 'WhiteWins');",
+            "INSERT INTO settings (id, extra) VALUES (1, '{\"inactive_after_rounds\": 1}');"
+        ))
+        .unwrap();
+        let st =
+            standings_internal(&conn, "2019-06-18".into(), StandingsOptions::default()).unwrap();
+        let order: Vec<_> = st.players.iter().map(|p| (p.id, p.inactive)).collect();
+        assert_eq!(order, [(41, false), (42, false), (43, true)]);
+        assert!(!st.hide_inactive);
+        assert_eq!(st.players[1].results[0][0].other_place, 1);
+    }
+
+    fn ordered(conn: &rusqlite::Connection, order: StandingsOrder, min_games: u32) -> Vec<i32> {
+        let options = StandingsOptions {
+            order,
+            min_games,
+            ..StandingsOptions::default()
+        };
+        let st = standings_internal(conn, "2019-06-18".into(), options).unwrap();
+        st.players.iter().map(|p| p.id).collect()
+    }
+
+    #[test]
+    fn calc_standings_order() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1000.0, 1100.0), ",
+            "(43, \"player3\", 1000.0, 1050.0), ",
+            "(44, \"player4\", 1000.0, 1200.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(31, 99, 41, 42, 'WhiteWins'), ",
+            "(32, 99, 43, 44, 'WhiteWins'), ",
+            "(33, 99, 42, 44, 'WhiteWins'), ",
+            "(34, 99, 44, 41, 'BlackWinsByDefault');"
+        ))
+        .unwrap();
+        assert_eq!(ordered(&conn, StandingsOrder::Rating, 1), [44, 42, 43, 41]);
+        assert_eq!(ordered(&conn, StandingsOrder::Score, 1), [41, 42, 43, 44]);
+        assert_eq!(
+            ordered(&conn, StandingsOrder::WinPercentage, 2),
+            [41, 42, 44, 43]
+        );
+        assert_eq!(
+            ordered(&conn, StandingsOrder::WinPercentage, 1),
+            [43, 41, 42, 44]
+        );
+        assert_eq!(ordered(&conn, StandingsOrder::Points, 1), [41, 42, 43, 44]);
+        let options = StandingsOptions {
+            order: StandingsOrder::Points,
+            ..StandingsOptions::default()
+        };
+        let st = standings_internal(&conn, "2019-06-18".into(), options).unwrap();
+        let p = &st.players[1];
+        // The forfeit does not count for the tiebreakers.
+        assert_eq!((p.id, p.score, p.sos, p.sodos), (42, 1.0, 2.0, 0.0));
+        let p = &st.players[0];
+        assert_eq!((p.id, p.score, p.sos, p.sodos), (41, 2.0, 1.0, 1.0));
+        assert_eq!(p.win_percentage().unwrap().0, 1.0);
+        // Opponents are shown with their places in this order.
+        let p = &st.players[2];
+        assert_eq!(p.results[0][0].other_place, 4);
+    }
+
+    #[test]
+    fn sort_players_head_to_head() {
+        let player = |id| StandingsPlayer {
+            id,
+            original_index: 1,
+            name: String::new(),
+            default_schedule: false,
+            initialrating: Rating(1000.0),
+            currentrating: Rating(1000.0),
+            provisional: false,
+            inactive: false,
+            results: Vec::new(),
+            score: 1.0,
+            games: 2,
+            sos: 2.0,
+            sodos: 1.0,
+        };
+        let mut players = vec![player(1), player(2), player(3)];
+        let played = vec![(3, vec![(2, 1.0)]), (2, vec![(3, 0.0)])]
+            .into_iter()
+            .collect();
+        let options = StandingsOptions {
+            order: StandingsOrder::Points,
+            ..StandingsOptions::default()
+        };
+        sort_players(&mut players, &options, &played);
+        let order: Vec<_> = players.iter().map(|p| p.id).collect();
+        assert_eq!(order, [3, 1, 2]);
+    }
+
+    #[test]
+    fn standings_options() {
+        let params = |list: &[(&str, &str)]| -> HashMap<String, String> {
+            list.iter()
+                .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+                .collect()
+        };
+        let options = StandingsOptions::from_params(&params(&[])).unwrap();
+        assert_eq!(options.order, StandingsOrder::Rating);
+        let options =
+            StandingsOptions::from_params(&params(&[("order", "percentage"), ("min_games", "5")]))
+                .unwrap();
+        assert_eq!(options.order, StandingsOrder::WinPercentage);
+        assert_eq!(options.min_games, 5);
+        assert!(StandingsOptions::from_params(&params(&[("order", "name")])).is_err());
+        assert!(StandingsOptions::from_params(&params(&[("min_games", "-1")])).is_err());
+        let options = StandingsOptions::from_params(&params(&[("as_of", "2019-03-31")])).unwrap();
+        assert_eq!(options.as_of.as_deref(), Some("2019-03-31"));
+        for &date in &["2019-3-31", "2019-13-01", "March"] {
+            assert!(StandingsOptions::from_params(&params(&[("as_of", date)])).is_err());
+            assert!(StandingsOptions::from_params(&params(&[("from", date)])).is_err());
+        }
+        let options = StandingsOptions::from_params(&params(&[("month", "2020-02")])).unwrap();
+        assert_eq!(options.from.as_deref(), Some("2020-02-01"));
+        assert_eq!(options.as_of.as_deref(), Some("2020-02-29"));
+        let options = StandingsOptions::from_params(&params(&[("month", "2019-12")])).unwrap();
+        assert_eq!(options.as_of.as_deref(), Some("2019-12-31"));
+        for &month in &["2019-13", "2019-1", "2019-01-01"] {
+            assert!(StandingsOptions::from_params(&params(&[("month", month)])).is_err());
+        }
+        assert!(StandingsOptions::from_params(&params(&[
+            ("month", "2019-03"),
+            ("as_of", "2019-03-31")
+        ]))
+        .is_err());
+        assert!(StandingsOptions::from_params(&params(&[
+            ("from", "2019-04-01"),
+            ("as_of", "2019-03-31")
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn calc_standings_as_of() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1000.0, 1000.0), ",
+            "(43, \"player3\", 900.0, 900.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES ",
+            "(98, '2019-03-11'), (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(32, 98, 42, 41, 'WhiteWins'), ",
+            "(33, 99, 43, 42, 'WhiteWins'), ",
+            "(34, 99, 43, 41, 'WhiteWins');"
+        ))
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        crate::update_ratings::update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let options = StandingsOptions {
+            as_of: Some("2019-03-31".to_owned()),
+            ..StandingsOptions::default()
+        };
+        let st = standings_internal(&conn, "2019-03-31".into(), options).unwrap();
+        assert_eq!(st.today, "2019-03-31");
+        assert_eq!(st.rounds.len(), 1);
+        assert_eq!(st.games, 1);
+        let order: Vec<_> = st.players.iter().map(|p| (p.id, p.games)).collect();
+        assert_eq!(order, [(42, 1), (41, 1), (43, 0)]);
+        let p = &st.players[0];
+        assert!(p.currentrating.0 > 1000.0);
+        assert_eq!(p.score, 1.0);
+        // Player 3 has not played yet and keeps the initial rating.
+        assert_eq!(st.players[2].currentrating.0, 900.0);
+        assert_eq!(st.players[2].place_diff(3).to_string(), "+0");
+        // The present standings include the later games.
+        let st =
+            standings_internal(&conn, "2019-06-18".into(), StandingsOptions::default()).unwrap();
+        assert_eq!(st.games, 3);
+        let p = st.players.iter().find(|p| p.id == 43).unwrap();
+        assert!(p.currentrating.0 > 900.0);
+    }
+
+    #[test]
+    fn calc_standings_range() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1000.0, 1000.0), ",
+            "(43, \"player3\", 900.0, 900.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES ",
+            "(97, '2019-05-27'), (98, '2019-06-03'), (99, '2019-06-17'), (100, '2019-07-01');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(31, 97, 42, 41, 'WhiteWins'), ",
+            "(32, 98, 43, 42, 'WhiteWins'), ",
+            "(33, 99, 43, 41, 'WhiteWins'), ",
+            "(34, 100, 41, 42, 'WhiteWins');"
+        ))
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        crate::update_ratings::update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let mut params = HashMap::new();
+        params.insert("month".to_owned(), "2019-06".to_owned());
+        params.insert("order".to_owned(), "score".to_owned());
+        let options = StandingsOptions::from_params(&params).unwrap();
+        let today = options.as_of.clone().unwrap();
+        let st = standings_internal(&conn, today, options).unwrap();
+        assert_eq!(st.today, "2019-06-30");
+        assert_eq!(st.games, 2);
+        assert_eq!(st.rounds.len(), 2);
+        let scores: Vec<_> = st
+            .players
+            .iter()
+            .map(|p| (p.id, p.score, p.games))
+            .collect();
+        assert_eq!(scores, [(43, 2.0, 2), (42, 0.0, 1), (41, 0.0, 1)]);
+        let start = ratings_as_of(&conn, &load_settings(&conn).unwrap(), "2019-05-31").unwrap();
+        let end = ratings_as_of(&conn, &load_settings(&conn).unwrap(), "2019-06-30").unwrap();
+        for p in &st.players {
+            // The rating change is counted within the month.
+            assert_eq!(p.initialrating.0, start[&p.id].0);
+            assert_eq!(p.currentrating.0, end[&p.id].0);
+        }
+        // Places are compared with the order by rating at the start of June.
+        let places: Vec<_> = st.players.iter().map(|p| p.original_index).collect();
+        assert_eq!(places, [3, 1, 2]);
+        // Player 3 won all games in June, the others lost all.
+        assert!(st.performances.is_empty());
+        let st =
+            standings_internal(&conn, "2019-07-02".into(), StandingsOptions::default()).unwrap();
+        assert!(st.performance(&43).is_none());
+        // Player 2 won one of three games against players rated 1000 or
+        // less.
+        assert!(st.performance(&42).unwrap().0 < 1000.0);
+        assert_eq!(st.months.0.len(), 4);
+        let months: Vec<_> = st.months.into_iter().map(|m| m.year_and_month).collect();
+        assert_eq!(months, ["2019-05", "2019-06", "2019-07"]);
+    }
+
+    #[test]
+    fn standings_csv_json() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"Doe, John\", 1000.0, 1020.0), ",
+            "(42, \"player2\", 1000.0, 980.0), ",
+            "(43, \"player3\", 900.0, 900.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (98, '2019-06-10'), (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(33, 99, 41, 42, 'WhiteWins');"
+        ))
+        .unwrap();
+        let st =
+            standings_internal(&conn, "2019-06-18".into(), StandingsOptions::default()).unwrap();
+        let csv = standings_csv(&st);
+        let lines: Vec<_> = csv.split_terminator("\r\n").collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "Place,Place change,Name,Default schedule,Rating,Rating change,Rank,2019-06-17,Score,Games,Win %,Performance,SOS,SODOS"
+        );
+        assert_eq!(
+            lines[1],
+            "1,0,\"Doe, John\",no,1020,20,11k,2/w0+,1,1,100%,,0,0"
+        );
+        assert_eq!(lines[3], "3,0,player3,no,900,0,12k,,0,0,,,0,0");
+        let json = serde_json::to_value(standings_export(&st)).unwrap();
+        assert_eq!(json["date"], "2019-06-18");
+        assert_eq!(json["from"], serde_json::Value::Null);
+        assert_eq!(json["rounds"][0]["date"], "2019-06-17");
+        let p = &json["players"][1];
+        assert_eq!(p["place"], 2);
+        assert_eq!(p["name"], "player2");
+        assert_eq!(p["rating"], 980.0);
+        assert_eq!(p["rating_change"], -20.0);
+        assert_eq!(p["rank"], "11k");
+        assert_eq!(
+            p["results"][0][0],
+            serde_json::json!({
+                "opponent_place": 1,
+                "colour": "black",
+                "handicap": "0w6½",
+                "effective_handicap": 0.0,
+                "result": "loss"
+            })
+        );
+        let handicap: Handicap =
+            serde_json::from_value(p["results"][0][0]["handicap"].clone()).unwrap();
+        assert_eq!(handicap.to_f64(), 0.0);
+        assert_eq!(
+            json["players"][2]["win_percentage"],
+            serde_json::Value::Null
+        );
+    }
 }
@@ -0,0 +1,260 @@
+//! Export of the public pages to a directory of static HTML files, which can
+//! be published without exposing the application itself
+use std::fs;
+use std::path::Path;
+
+use askama::Template;
+
+use gorating::{Handicap, Rating};
+
+use crate::models::{FormattableGameResult, GameResult, Links, Player, Round, RoundsByMonth};
+use crate::{crosstable, profile, standings, CommonTemplate, Error, Result, StaticAsset};
+
+#[derive(Template)]
+#[template(path = "static/index.html")]
+struct StaticIndexTemplate {
+    rounds: RoundsByMonth,
+}
+impl CommonTemplate for StaticIndexTemplate {
+    fn links(&self) -> Links {
+        Links::Static { root: "" }
+    }
+}
+
+/// A game as shown in the results of a round
+#[derive(Debug)]
+pub struct RoundGame {
+    pub black: Player,
+    pub white: Player,
+    pub handicap: Handicap,
+    pub boardsize: u32,
+    pub result: FormattableGameResult,
+}
+
+#[derive(Template)]
+#[template(path = "static/round.html")]
+struct StaticRoundTemplate<'a> {
+    round: &'a Round,
+    games: Vec<RoundGame>,
+}
+impl<'a> CommonTemplate for StaticRoundTemplate<'a> {
+    fn links(&self) -> Links {
+        Links::Static { root: "../" }
+    }
+}
+
+fn load_rounds(conn: &rusqlite::Connection) -> Result<Vec<Round>> {
+    let mut stmt =
+        conn.prepare("SELECT id, CAST(date AS TEXT), extra FROM rounds ORDER BY date")?;
+    let rounds: Vec<Round> = stmt
+        .query_map([], |row| {
+            Ok(Round {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                extra: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    if rounds.iter().any(|round| round.date.len() != 10) {
+        return Err(Error::Inconsistency("invalid round date"));
+    }
+    Ok(rounds)
+}
+
+fn load_round_games(conn: &rusqlite::Connection, round_id: i32) -> Result<Vec<RoundGame>> {
+    let mut stmt = conn.prepare(concat!(
+        "SELECT b.id, b.name, b.currentrating, w.id, w.name, w.currentrating, ",
+        "g.handicap, g.boardsize, g.result ",
+        "FROM games g, players b, players w ",
+        "WHERE g.played = ?1 AND g.black = b.id AND g.white = w.id ",
+        "ORDER BY g.id"
+    ))?;
+    let games = stmt
+        .query_map([round_id], |row| {
+            let result: Option<GameResult> = row.get(8)?;
+            Ok(RoundGame {
+                black: Player {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    rating: Rating::new(row.get(2)?),
+                },
+                white: Player {
+                    id: row.get(3)?,
+                    name: row.get(4)?,
+                    rating: Rating::new(row.get(5)?),
+                },
+                handicap: Handicap::new(row.get(6)?),
+                boardsize: row.get(7)?,
+                result: FormattableGameResult(result),
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(games)
+}
+
+/// Render all pages, as pairs of a path relative to the output directory and
+/// the contents
+fn render_pages(conn: &rusqlite::Connection, today: &str) -> Result<Vec<(String, String)>> {
+    let mut pages = Vec::new();
+    let rounds = load_rounds(conn)?;
+    for round in &rounds {
+        let page = StaticRoundTemplate {
+            round,
+            games: load_round_games(conn, round.id)?,
+        };
+        pages.push((format!("rounds/{}.html", round.id), page.render()?));
+    }
+    let index = StaticIndexTemplate {
+        rounds: RoundsByMonth(rounds),
+    };
+    pages.push(("index.html".to_owned(), index.render()?));
+    pages.push((
+        "standings.html".to_owned(),
+        standings::render_static(conn, today)?,
+    ));
+    pages.push((
+        "crosstable.html".to_owned(),
+        crosstable::render_static(conn)?,
+    ));
+    let mut stmt = conn.prepare("SELECT id FROM players ORDER BY id")?;
+    let ids: Vec<i32> = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for id in ids {
+        pages.push((
+            format!("players/{}.html", id),
+            profile::render_static(conn, id, today)?,
+        ));
+    }
+    Ok(pages)
+}
+
+/// Remove the contents of an earlier export from `dir`, so that pages of
+/// deleted rounds or players do not survive. A directory that is neither
+/// empty nor an earlier export is left alone.
+fn clear_output(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    if !entries.is_empty() && !dir.join("index.html").is_file() {
+        return Err(Error::BadParam(
+            "output directory is not empty and not an earlier export",
+        ));
+    }
+    for entry in entries {
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the public pages and the embedded static assets to `dir`, which is
+/// created if necessary and replaces an earlier export in it. Returns the
+/// number of files written.
+pub(crate) fn export(conn: &rusqlite::Connection, dir: &Path, today: &str) -> Result<usize> {
+    let mut files: Vec<(String, Vec<u8>)> = render_pages(conn, today)?
+        .into_iter()
+        .map(|(path, html)| (path, html.into_bytes()))
+        .collect();
+    for path in StaticAsset::iter() {
+        let content = StaticAsset::get(&path).unwrap();
+        files.push((format!("static/{}", path), content.data.into_owned()));
+    }
+    clear_output(dir)?;
+    for (path, content) in &files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    #[test]
+    fn render_pages_1() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1020.0), ",
+            "(42, \"player2\", 1000.0, 980.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (98, '2019-06-10'), (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(33, 98, 41, 42, 'WhiteWins'), ",
+            "(34, 99, 42, 41, NULL);"
+        ))
+        .unwrap();
+        let pages = render_pages(&conn, "2019-06-18").unwrap();
+        let paths: Vec<_> = pages.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "rounds/98.html",
+                "rounds/99.html",
+                "index.html",
+                "standings.html",
+                "crosstable.html",
+                "players/41.html",
+                "players/42.html"
+            ]
+        );
+        let page = |path| &pages.iter().find(|(p, _)| p == path).unwrap().1;
+        assert!(page("index.html").contains("href=\"rounds/98.html\""));
+        let round = page("rounds/98.html");
+        assert!(round.contains("href=\"../static/css/default.css\""));
+        assert!(round.contains("href=\"../players/42.html\""));
+        assert!(round.contains("0-1"));
+        assert!(page("standings.html").contains("href=\"players/41.html\""));
+        assert!(page("players/41.html").contains("href=\"../rounds/98.html\""));
+        // Nothing links back to the application.
+        for (_, html) in &pages {
+            assert!(!html.contains("href=\"/"));
+            assert!(!html.contains("<form"));
+        }
+    }
+
+    #[test]
+    fn export_1() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let dir = std::env::temp_dir().join(format!("goladder-static-{}", std::process::id()));
+        let count = export(&conn, &dir, "2019-06-18").unwrap();
+        assert!(dir.join("index.html").is_file());
+        assert!(dir.join("static/css/default.css").is_file());
+        assert_eq!(count, 3 + StaticAsset::iter().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_replaces_earlier_export() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let dir = std::env::temp_dir().join(format!("goladder-stale-{}", std::process::id()));
+        export(&conn, &dir, "2019-06-18").unwrap();
+        fs::create_dir_all(dir.join("players")).unwrap();
+        fs::write(dir.join("players/41.html"), "deleted player").unwrap();
+        fs::write(dir.join("old.html"), "stale").unwrap();
+        export(&conn, &dir, "2019-06-18").unwrap();
+        assert!(dir.join("index.html").is_file());
+        assert!(!dir.join("players").exists());
+        assert!(!dir.join("old.html").exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Other files are not removed.
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), "keep").unwrap();
+        assert!(export(&conn, &dir, "2019-06-18").is_err());
+        assert!(dir.join("notes.txt").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
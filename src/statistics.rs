@@ -0,0 +1,435 @@
+//! Statistics page comparing actual results with the rating system
+use std::collections::BTreeMap;
+
+use actix_web::Responder;
+use askama::Template;
+
+use gorating::{Rank, RankScale, Rating};
+
+use crate::db::load_settings;
+use crate::models::Percentage;
+use crate::update_ratings::{rated_games, RatedGame};
+use crate::{CommonTemplate, Result};
+
+/// Width of the rating bands
+const BAND_WIDTH: f64 = 500.0;
+
+/// Height of the highest bar of a chart in pixels
+const CHART_HEIGHT: usize = 120;
+const BAR_WIDTH: usize = 24;
+const BAR_GAP: usize = 6;
+
+/// Results of a group of games for black
+#[derive(Debug)]
+pub struct StatisticsRow {
+    pub label: String,
+    pub games: u32,
+    /// Wins for black, with jigo counting as half a win
+    pub black_score: f64,
+    /// Sum of the expected results for black
+    pub black_expected: f64,
+}
+
+impl StatisticsRow {
+    fn new(label: String) -> Self {
+        StatisticsRow {
+            label,
+            games: 0,
+            black_score: 0.0,
+            black_expected: 0.0,
+        }
+    }
+
+    fn add(&mut self, game: &RatedGame) {
+        self.games += 1;
+        self.black_score += 1.0 - game.game.white_result;
+        self.black_expected += game.black_expected;
+    }
+
+    pub fn actual(&self) -> Percentage {
+        Percentage(self.black_score / f64::from(self.games))
+    }
+
+    pub fn predicted(&self) -> Percentage {
+        Percentage(self.black_expected / f64::from(self.games))
+    }
+}
+
+/// A bar chart, displayed as inline SVG
+#[derive(Debug, Default)]
+pub struct BarChart {
+    /// Label and value of each bar
+    pub bars: Vec<(String, u32)>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl std::fmt::Display for BarChart {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let max = self.bars.iter().map(|bar| bar.1).max().unwrap_or(0).max(1) as usize;
+        // Long labels such as dates are written vertically.
+        let vertical = self.bars.iter().any(|(label, _)| label.chars().count() > 4);
+        let top = 16;
+        let bottom = top + CHART_HEIGHT;
+        let width = self.bars.len() * (BAR_WIDTH + BAR_GAP) + BAR_GAP;
+        let height = bottom + if vertical { 70 } else { 20 };
+        write!(
+            formatter,
+            r#"<svg class="chart" width="{0}" height="{1}" viewBox="0 0 {0} {1}" xmlns="http://www.w3.org/2000/svg">"#,
+            width, height
+        )?;
+        write!(
+            formatter,
+            r#"<line x1="0" y1="{0}" x2="{1}" y2="{0}"/>"#,
+            bottom, width
+        )?;
+        for (i, (label, value)) in self.bars.iter().enumerate() {
+            let label = escape(label);
+            let x = BAR_GAP + i * (BAR_WIDTH + BAR_GAP);
+            let middle = x + BAR_WIDTH / 2;
+            let bar_height = (*value as usize * CHART_HEIGHT + max / 2) / max;
+            let y = bottom - bar_height;
+            write!(
+                formatter,
+                r#"<rect x="{}" y="{}" width="{}" height="{}"><title>{}: {}</title></rect>"#,
+                x, y, BAR_WIDTH, bar_height, label, value
+            )?;
+            write!(
+                formatter,
+                r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+                middle,
+                y - 3,
+                value
+            )?;
+            if vertical {
+                write!(
+                    formatter,
+                    r#"<text x="{0}" y="{1}" text-anchor="end" transform="rotate(-90 {0} {1})">{2}</text>"#,
+                    middle + 4,
+                    bottom + 4,
+                    label
+                )?;
+            } else {
+                write!(
+                    formatter,
+                    r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+                    middle,
+                    bottom + 14,
+                    label
+                )?;
+            }
+        }
+        write!(formatter, "</svg>")
+    }
+}
+
+#[derive(Template)]
+#[template(path = "statistics.html")]
+struct StatisticsTemplate {
+    total: StatisticsRow,
+    by_handicap: Vec<StatisticsRow>,
+    by_rating: Vec<StatisticsRow>,
+    /// Mean squared difference between expected and actual results
+    brier_score: f64,
+    /// Mean negative log-likelihood of the actual results
+    log_loss: f64,
+    rank_scale: RankScale,
+    /// Number of players by the rank of their current rating
+    ranks: BarChart,
+    /// Number of players by their score, in players who played any games
+    scores: BarChart,
+    /// Number of games with a result in each round
+    games_per_round: BarChart,
+    /// Results of all games, including forfeits
+    results: BarChart,
+}
+impl CommonTemplate for StatisticsTemplate {}
+
+pub(crate) fn statistics(conn: &rusqlite::Connection) -> Result<impl Responder> {
+    statistics_internal(conn)
+}
+
+/// Label of the handicap including the board size, sorted by the value of
+/// the handicap
+fn handicap_key(game: &RatedGame) -> ((u32, i64), String) {
+    let handicap = game.game.handicap;
+    let board_size = game.game.board_size;
+    let label = if board_size == 19 {
+        handicap.to_string()
+    } else {
+        format!("{} ({}×{})", handicap, board_size, board_size)
+    };
+    let order = (handicap.effective() * 1000.0).round() as i64;
+    ((u32::MAX - board_size, order), label)
+}
+
+/// Label of a rating band with the ranks of its lowest and highest rating
+fn band_label(band: f64, scale: RankScale) -> String {
+    let low = band * BAND_WIDTH;
+    let high = low + BAND_WIDTH - 1.0;
+    format!(
+        "{}–{} ({}–{})",
+        low,
+        high,
+        scale.rank(Rating(low)),
+        scale.rank(Rating(high))
+    )
+}
+
+/// Rating band of the average rating of both players
+fn rating_key(game: &RatedGame, scale: RankScale) -> (i64, String) {
+    let average = (game.delta.white_rating.0 + game.delta.black_rating.0) / 2.0;
+    let band = (average / BAND_WIDTH).floor();
+    (band as i64, band_label(band, scale))
+}
+
+fn group<K: Ord>(
+    games: &[RatedGame],
+    key: impl Fn(&RatedGame) -> (K, String),
+) -> Vec<StatisticsRow> {
+    let mut rows = BTreeMap::new();
+    for game in games {
+        let (k, label) = key(game);
+        rows.entry(k)
+            .or_insert_with(|| StatisticsRow::new(label))
+            .add(game);
+    }
+    rows.into_values().collect()
+}
+
+/// Bars for all keys from the lowest to the highest key in `counts`, so that
+/// gaps in the distribution are visible
+fn histogram(counts: &BTreeMap<i64, u32>, label: impl Fn(i64) -> String) -> BarChart {
+    let keys = match (counts.keys().next(), counts.keys().next_back()) {
+        (Some(&low), Some(&high)) => low..=high,
+        _ => return BarChart::default(),
+    };
+    BarChart {
+        bars: keys
+            .map(|key| (label(key), counts.get(&key).copied().unwrap_or(0)))
+            .collect(),
+    }
+}
+
+fn rank_chart(conn: &rusqlite::Connection, scale: RankScale) -> Result<BarChart> {
+    let mut stmt = conn.prepare("SELECT currentrating FROM players")?;
+    let mut counts = BTreeMap::new();
+    for rating in stmt.query_map([], |row| row.get(0))? {
+        let rank = scale.rank(Rating(rating?)).to_rating().0 / 100.0;
+        *counts.entry(rank as i64).or_default() += 1;
+    }
+    Ok(histogram(&counts, |rank| {
+        Rank::from_rating(Rating(rank as f64 * 100.0)).to_string()
+    }))
+}
+
+/// Score as shown in a chart, such as "3½"
+fn half_points(half_points: i64) -> String {
+    match (half_points / 2, half_points % 2) {
+        (0, 1) => "½".to_owned(),
+        (points, 1) => format!("{}½", points),
+        (points, _) => points.to_string(),
+    }
+}
+
+fn score_chart(conn: &rusqlite::Connection) -> Result<BarChart> {
+    let mut stmt = conn.prepare(concat!(
+        "SELECT COUNT((p.id = g.black AND g.result IN ('BlackWins', 'BlackWinsByDefault')) ",
+        "OR (p.id = g.white AND g.result IN ('WhiteWins', 'WhiteWinsByDefault')) OR NULL), ",
+        "COUNT(g.result = 'Jigo' OR NULL) ",
+        "FROM players p, games g ",
+        "WHERE (p.id = g.black OR p.id = g.white) AND g.result IS NOT NULL ",
+        "GROUP BY p.id"
+    ))?;
+    let mut counts = BTreeMap::new();
+    for score in stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))? {
+        let (wins, jigos) = score?;
+        *counts.entry(2 * wins + jigos).or_default() += 1;
+    }
+    if !counts.is_empty() {
+        counts.entry(0).or_default();
+    }
+    Ok(histogram(&counts, half_points))
+}
+
+/// Games in each round up to the last round with results
+fn games_per_round_chart(conn: &rusqlite::Connection) -> Result<BarChart> {
+    let mut stmt = conn.prepare(concat!(
+        "SELECT CAST(r.date AS TEXT), COUNT(g.id) FROM rounds r ",
+        "LEFT OUTER JOIN games g ON g.played = r.id AND g.result IS NOT NULL ",
+        "WHERE r.date <= (SELECT MAX(r2.date) FROM rounds r2, games g2 ",
+        "WHERE g2.played = r2.id AND g2.result IS NOT NULL) ",
+        "GROUP BY r.id ORDER BY r.date, r.id"
+    ))?;
+    let bars = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(BarChart { bars })
+}
+
+fn results_chart(conn: &rusqlite::Connection) -> Result<BarChart> {
+    let (white_wins, black_wins, jigo, forfeit) = conn.query_row(
+        concat!(
+            "SELECT COUNT(result = 'WhiteWins' OR NULL), COUNT(result = 'BlackWins' OR NULL), ",
+            "COUNT(result = 'Jigo' OR NULL), ",
+            "COUNT(result IN ('WhiteWinsByDefault', 'BlackWinsByDefault', 'BothLose') OR NULL) ",
+            "FROM games"
+        ),
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+    Ok(BarChart {
+        bars: vec![
+            ("White wins".to_owned(), white_wins),
+            ("Black wins".to_owned(), black_wins),
+            ("Jigo".to_owned(), jigo),
+            ("Forfeit".to_owned(), forfeit),
+        ],
+    })
+}
+
+fn statistics_internal(conn: &rusqlite::Connection) -> Result<StatisticsTemplate> {
+    let settings = load_settings(conn)?;
+    let games = rated_games(conn, &settings)?;
+    let mut total = StatisticsRow::new("All games".to_owned());
+    let (mut brier_sum, mut log_loss_sum) = (0.0, 0.0);
+    for game in &games {
+        total.add(game);
+        let p = game.black_expected.clamp(1e-6, 1.0 - 1e-6);
+        let outcome = 1.0 - game.game.white_result;
+        brier_sum += (p - outcome) * (p - outcome);
+        log_loss_sum -= outcome * p.ln() + (1.0 - outcome) * (1.0 - p).ln();
+    }
+    let n = f64::from(total.games.max(1));
+    Ok(StatisticsTemplate {
+        by_handicap: group(&games, handicap_key),
+        by_rating: group(&games, |game| rating_key(game, settings.rank_scale)),
+        total,
+        brier_score: brier_sum / n,
+        log_loss: log_loss_sum / n,
+        rank_scale: settings.rank_scale,
+        ranks: rank_chart(conn, settings.rank_scale)?,
+        scores: score_chart(conn)?,
+        games_per_round: games_per_round_chart(conn)?,
+        results: results_chart(conn)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ensure_schema;
+
+    #[test]
+    fn statistics_empty() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let st = statistics_internal(&conn).unwrap();
+        assert_eq!(st.total.games, 0);
+        assert!(st.by_handicap.is_empty());
+        assert!(st.by_rating.is_empty());
+        assert_eq!(st.brier_score, 0.0);
+        assert!(st.ranks.bars.is_empty());
+        assert!(st.scores.bars.is_empty());
+        assert!(st.games_per_round.bars.is_empty());
+        assert_eq!(st.results.bars.iter().map(|bar| bar.1).sum::<u32>(), 0);
+    }
+
+    #[test]
+    fn statistics_1() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1000.0), ",
+            "(42, \"player2\", 1000.0, 1000.0), ",
+            "(43, \"player3\", 1300.0, 1300.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES (99, '2019-06-17');",
+            "INSERT INTO games (id, played, white, black, handicap, result) VALUES ",
+            "(32, 99, 41, 42, 0.0, 'WhiteWins'), ",
+            "(33, 99, 43, 41, 3.5, 'BlackWins'), ",
+            "(34, 99, 43, 42, 3.5, 'Jigo'), ",
+            "(35, 99, 42, 41, 0.0, 'BlackWinsByDefault');"
+        ))
+        .unwrap();
+        let st = statistics_internal(&conn).unwrap();
+        assert_eq!(st.total.games, 3);
+        assert_eq!(st.total.black_score, 1.5);
+        let labels: Vec<_> = st.by_handicap.iter().map(|r| r.label.as_str()).collect();
+        assert_eq!(labels, ["0w6½", "3b5"]);
+        assert_eq!(st.by_handicap[0].games, 1);
+        assert_eq!(st.by_handicap[0].actual().0, 0.0);
+        assert_eq!(st.by_handicap[0].predicted().0, 0.5);
+        assert_eq!(st.by_handicap[1].actual().0, 0.75);
+        let labels: Vec<_> = st.by_rating.iter().map(|r| r.label.as_str()).collect();
+        assert_eq!(labels, ["1000–1499 (11k–6k)"]);
+        // An even game that black lost with an expected result of 0.5
+        // contributes 0.25 to the Brier score.
+        assert!(st.brier_score > 0.0 && st.brier_score < 0.25);
+        assert!(st.log_loss > 0.0);
+    }
+
+    #[test]
+    fn band_labels() {
+        // 1499 rounds to 6k and 1999 to 1k.
+        assert_eq!(band_label(3.0, RankScale::Egd), "1500–1999 (6k–1k)");
+        assert_eq!(band_label(4.0, RankScale::Egd), "2000–2499 (1k–5d)");
+        assert_eq!(band_label(3.0, RankScale::Aga), "1500–1999 (5k–1d)");
+    }
+
+    #[test]
+    fn statistics_charts() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(41, \"player1\", 1000.0, 1020.0), ",
+            "(42, \"player2\", 1000.0, 980.0), ",
+            "(43, \"player3\", 1300.0, 1300.0), ",
+            "(44, \"player4\", 1300.0, 1300.0);",
+            "INSERT INTO rounds (id, \"date\") VALUES ",
+            "(97, '2019-06-03'), (98, '2019-06-10'), (99, '2019-06-17'), (100, '2019-06-24');",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(32, 97, 41, 42, 'WhiteWins'), ",
+            "(33, 97, 43, 44, 'Jigo'), ",
+            "(34, 99, 43, 41, 'BlackWinsByDefault'), ",
+            "(35, 100, 42, 44, NULL);"
+        ))
+        .unwrap();
+        let st = statistics_internal(&conn).unwrap();
+        let expected = |bars: &[(&str, u32)]| -> Vec<(String, u32)> {
+            bars.iter().map(|&(l, v)| (l.to_owned(), v)).collect()
+        };
+        assert_eq!(
+            st.ranks.bars,
+            expected(&[("11k", 2), ("10k", 0), ("9k", 0), ("8k", 2)])
+        );
+        assert_eq!(
+            st.scores.bars,
+            expected(&[("0", 1), ("½", 2), ("1", 0), ("1½", 0), ("2", 1)])
+        );
+        assert_eq!(
+            st.games_per_round.bars,
+            expected(&[("2019-06-03", 2), ("2019-06-10", 0), ("2019-06-17", 1)])
+        );
+        assert_eq!(
+            st.results.bars,
+            expected(&[
+                ("White wins", 1),
+                ("Black wins", 0),
+                ("Jigo", 1),
+                ("Forfeit", 1)
+            ])
+        );
+        let svg = st.ranks.to_string();
+        assert!(svg.starts_with("<svg class=\"chart\" width=\"126\""));
+        assert!(svg.contains("<rect x=\"6\" y=\"16\" width=\"24\" height=\"120\">"));
+        assert!(svg.contains("<rect x=\"36\" y=\"136\" width=\"24\" height=\"0\">"));
+        assert!(st.games_per_round.to_string().contains("rotate(-90"));
+    }
+}
@@ -1,75 +1,552 @@
-use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use gorating::{Rating, RatingSystem};
-use rusqlite::types::ToSql;
+use gorating::batch::{Batch, BatchResult, Game, GameDelta};
+use gorating::{Handicap, Rating};
+use rusqlite::types::{ToSql, Type};
 use rusqlite::Transaction;
 
-use crate::models::GameResult;
+use crate::db::load_settings;
+use crate::models::{GameResult, Settings};
 
-pub static RATINGS: RatingSystem = RatingSystem::new();
+/// Rated games grouped by the period in which the maximum rating drop
+/// applies, and within it by round
+type Periods = Vec<Vec<Vec<Game<i32>>>>;
 
-struct PendingRating {
-    rating: Rating,
-    pending: Cell<f64>,
+/// Rated games of the season
+struct SeasonGames {
+    periods: Periods,
+    /// Database ids of the games, in the same order as in `periods`
+    ids: Vec<i32>,
 }
 
-impl PendingRating {
-    fn new(rating: f64) -> Self {
-        PendingRating {
-            rating: Rating(rating),
-            pending: Cell::new(0.0),
-        }
-    }
-}
-
-fn apply_pending_changes(ratings: &mut HashMap<i32, PendingRating>) {
-    for pr in ratings.values_mut() {
-        let adj = f64::max(pr.pending.replace(0.0), -RATINGS.max_drop);
-        pr.rating = RATINGS.adjust_rating(pr.rating, adj);
+/// Result for white of a game that is rated, or `None` for other games
+fn white_result(result: GameResult) -> Option<f64> {
+    match result {
+        GameResult::WhiteWins => Some(1.0),
+        GameResult::BlackWins => Some(0.0),
+        GameResult::Jigo => Some(0.5),
+        _ => None,
     }
 }
 
-pub fn update_ratings(trans: &Transaction) -> rusqlite::Result<()> {
-    let mut stmt = trans.prepare("SELECT id, initialrating FROM players")?;
-    let mut ratings: HashMap<i32, PendingRating> = stmt
-        .query_map([], |row| Ok((row.get(0)?, PendingRating::new(row.get(1)?))))?
-        .collect::<rusqlite::Result<_>>()?;
+/// Load the rated games in chronological order, up to and including the
+/// rounds on `until` if given
+fn load_periods(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+    until: Option<&str>,
+) -> rusqlite::Result<SeasonGames> {
+    let mut periods: Periods = Vec::new();
+    let mut ids = Vec::new();
+    let mut last_period = None;
     let mut last_round = None;
-    let mut stmt = trans.prepare(
-        "SELECT g.white, g.black, g.handicap, g.boardsize, g.result, r.id FROM games g, rounds r WHERE g.played = r.id AND g.result IS NOT NULL ORDER BY r.date"
+    let mut stmt = conn.prepare(
+        "SELECT g.white, g.black, g.handicap, g.boardsize, g.result, r.id, CAST(r.date AS TEXT), g.id FROM games g, rounds r WHERE g.played = r.id AND g.result IS NOT NULL AND (?1 IS NULL OR r.date <= ?1) ORDER BY r.date, r.id, g.id"
     )?;
-    stmt.query_map([], |row| {
+    stmt.query_map([until], |row| {
         let white: i32 = row.get(0)?;
         let black: i32 = row.get(1)?;
-        let handicap: f64 = row.get(2)?;
-        let _boardsize: i16 = row.get(3)?;
+        let handicap = Handicap::new(row.get(2)?);
+        let board_size: u32 = row.get(3)?;
         let result: GameResult = row.get(4)?;
         let round: i32 = row.get(5)?;
-        if Some(round) != last_round {
+        let date: String = row.get(6)?;
+        let id: i32 = row.get(7)?;
+        let (period_round, period_date) = settings.max_drop_period.key(round, &date);
+        let period = (period_round, period_date.to_owned());
+        if last_period.as_ref() != Some(&period) {
+            last_period = Some(period);
+            periods.push(Vec::new());
+        }
+        let rounds = periods.last_mut().unwrap();
+        if last_round != Some(round) {
             last_round = Some(round);
-            apply_pending_changes(&mut ratings);
+            rounds.push(Vec::new());
         }
-        let wresult = match result {
-            GameResult::WhiteWins => 1.0,
-            GameResult::BlackWins => 0.0,
-            GameResult::Jigo => 0.5,
-            _ => return Ok(()),
+        let white_result = match white_result(result) {
+            Some(white_result) => white_result,
+            None => return Ok(()),
         };
-        let bresult = 1.0 - wresult;
-        let wpr = &ratings[&white];
-        let bpr = &ratings[&black];
-        let wadj = RATINGS.rating_adjustment(wpr.rating, bpr.rating, -handicap, wresult);
-        let badj = RATINGS.rating_adjustment(bpr.rating, wpr.rating, handicap, bresult);
-        wpr.pending.set(wpr.pending.get() + wadj);
-        bpr.pending.set(bpr.pending.get() + badj);
+        rounds.last_mut().unwrap().push(Game {
+            white,
+            black,
+            handicap,
+            board_size,
+            white_result,
+        });
+        ids.push(id);
         Ok(())
     })?
     .collect::<rusqlite::Result<()>>()?;
-    apply_pending_changes(&mut ratings);
+    Ok(SeasonGames { periods, ids })
+}
+
+/// Rate all games of the season up to `until` with the current settings
+fn rate_season(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+    until: Option<&str>,
+) -> rusqlite::Result<(SeasonGames, BatchResult<i32>)> {
+    let sys = settings.rating_system();
+    let mut stmt = conn.prepare("SELECT id, initialrating FROM players")?;
+    let initial_ratings: Vec<(i32, Rating)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, Rating(row.get(1)?))))?
+        .collect::<rusqlite::Result<_>>()?;
+    let games = load_periods(conn, settings, until)?;
+    let batch = Batch {
+        system: &sys,
+        provisional_games: settings.provisional_games,
+        provisional_factor: settings.provisional_factor,
+    };
+    // The games reference the players table, so an unknown player means
+    // that a game row cannot be read as such.
+    let result = batch
+        .rate_periods(initial_ratings, &games.periods)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Integer, Box::new(e)))?;
+    Ok((games, result))
+}
+
+/// Rate the season and store the current ratings, together with the ratings
+/// of both players before every rated game for the performance ratings
+pub fn update_ratings(trans: &Transaction) -> rusqlite::Result<()> {
+    let settings = load_settings(trans)?;
+    let (games, result) = rate_season(trans, &settings, None)?;
     let mut statement = trans.prepare("UPDATE players SET currentrating = ?2 WHERE id = ?1")?;
-    for (id, rating) in ratings.iter() {
-        statement.execute::<&[&dyn ToSql]>(&[&id, &rating.rating.0])?;
+    for (id, rating) in result.ratings.iter() {
+        statement.execute::<&[&dyn ToSql]>(&[&id, &rating.0])?;
+    }
+    trans.execute("DELETE FROM game_ratings", [])?;
+    let mut statement = trans
+        .prepare("INSERT INTO game_ratings (game, whiterating, blackrating) VALUES (?1, ?2, ?3)")?;
+    for (id, delta) in games.ids.iter().zip(result.games.iter().flatten()) {
+        statement.execute::<&[&dyn ToSql]>(&[id, &delta.white_rating.0, &delta.black_rating.0])?;
     }
     Ok(())
 }
+
+/// Ratings of all players after the rounds up to and including `date`,
+/// without changing the database
+pub fn ratings_as_of(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+    date: &str,
+) -> rusqlite::Result<HashMap<i32, Rating>> {
+    let (_, result) = rate_season(conn, settings, Some(date))?;
+    Ok(result.ratings)
+}
+
+/// A rated game with the ratings of both players before it
+#[derive(Debug)]
+pub struct RatedGame {
+    pub id: i32,
+    pub game: Game<i32>,
+    pub delta: GameDelta,
+    /// Expected result for black according to the rating system
+    pub black_expected: f64,
+    /// Expected result for white according to the rating system
+    pub white_expected: f64,
+}
+
+/// Rate all games of the season in chronological order, without changing
+/// the database
+pub fn rated_games(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+) -> rusqlite::Result<Vec<RatedGame>> {
+    let sys = settings.rating_system();
+    let (games, result) = rate_season(conn, settings, None)?;
+    let deltas = result.games.into_iter().flatten();
+    Ok(games
+        .ids
+        .into_iter()
+        .zip(games.periods.into_iter().flatten().flatten())
+        .zip(deltas)
+        .map(|((id, game), delta)| {
+            let handicap = sys.board_handicap(game.handicap.effective(), game.board_size);
+            RatedGame {
+                id,
+                black_expected: sys.expected_result(
+                    delta.black_rating,
+                    delta.white_rating,
+                    handicap,
+                ),
+                white_expected: sys.expected_result(
+                    delta.white_rating,
+                    delta.black_rating,
+                    -handicap,
+                ),
+                game,
+                delta,
+            }
+        })
+        .collect())
+}
+
+/// Performance rating of every player in the given games, against the
+/// ratings of the opponents at the time of each game
+pub fn performance_ratings<'a>(
+    settings: &Settings,
+    games: impl IntoIterator<Item = &'a RatedGame>,
+) -> HashMap<i32, Rating> {
+    performances(
+        settings,
+        games
+            .into_iter()
+            .map(|rg| (rg.game, rg.delta.white_rating, rg.delta.black_rating)),
+    )
+}
+
+/// Performance rating of every player in the games with the given ids, from
+/// the ratings stored by the last `update_ratings`
+pub fn stored_performance_ratings(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+    game_ids: &HashSet<i32>,
+) -> rusqlite::Result<HashMap<i32, Rating>> {
+    let mut stmt = conn.prepare(concat!(
+        "SELECT g.id, g.white, g.black, g.handicap, g.boardsize, g.result, ",
+        "gr.whiterating, gr.blackrating ",
+        "FROM games g, game_ratings gr WHERE g.id = gr.game AND g.result IS NOT NULL"
+    ))?;
+    let mut games = Vec::new();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: i32 = row.get(0)?;
+        let result: GameResult = row.get(5)?;
+        let white_result = match white_result(result) {
+            Some(white_result) if game_ids.contains(&id) => white_result,
+            _ => continue,
+        };
+        let game = Game {
+            white: row.get(1)?,
+            black: row.get(2)?,
+            handicap: Handicap::new(row.get(3)?),
+            board_size: row.get(4)?,
+            white_result,
+        };
+        games.push((game, Rating(row.get(6)?), Rating(row.get(7)?)));
+    }
+    Ok(performances(settings, games))
+}
+
+/// Performance ratings in games given with the ratings of white and black
+/// before each game
+fn performances(
+    settings: &Settings,
+    games: impl IntoIterator<Item = (Game<i32>, Rating, Rating)>,
+) -> HashMap<i32, Rating> {
+    let sys = settings.rating_system();
+    let mut results: HashMap<i32, Vec<(Rating, f64, f64)>> = HashMap::new();
+    for (game, white_rating, black_rating) in games {
+        let handicap = sys.board_handicap(game.handicap.effective(), game.board_size);
+        results.entry(game.black).or_default().push((
+            white_rating,
+            handicap,
+            1.0 - game.white_result,
+        ));
+        results
+            .entry(game.white)
+            .or_default()
+            .push((black_rating, -handicap, game.white_result));
+    }
+    results
+        .into_iter()
+        .filter_map(|(id, games)| Some((id, sys.performance_rating(&games)?)))
+        .collect()
+}
+
+/// Find the players whose rating is still provisional.
+pub fn provisional_players(
+    conn: &rusqlite::Connection,
+    settings: &Settings,
+) -> rusqlite::Result<HashSet<i32>> {
+    let mut stmt = conn.prepare(concat!(
+        "SELECT p.id FROM players p ",
+        "LEFT OUTER JOIN games g ON (p.id = g.white OR p.id = g.black) ",
+        "AND g.result IN ('WhiteWins', 'BlackWins', 'Jigo') ",
+        "GROUP BY p.id HAVING COUNT(g.id) < ?1"
+    ))?;
+    let players = stmt
+        .query_map([settings.provisional_games], |row| row.get(0))?
+        .collect();
+    players
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ensure_schema, save_settings};
+    use crate::models::{RatingFormula, RatingPeriod};
+    use gorating::RatingSystem;
+
+    /// The rating system with the default settings
+    const SYS: RatingSystem = RatingSystem::new();
+
+    fn setup(conn: &mut rusqlite::Connection) {
+        ensure_schema(conn).unwrap();
+        let trans = conn.transaction().unwrap();
+        trans
+            .execute_batch(concat!(
+                "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+                "(41, \"player1\", 1000.0, 1000.0), ",
+                "(42, \"player2\", 1000.0, 1000.0), ",
+                "(43, \"player3\", 1000.0, 1000.0);",
+                "INSERT INTO rounds (id, \"date\") VALUES ",
+                "(98, '2019-06-10'), (99, '2019-06-17');",
+                "INSERT INTO games (id, played, white, black, result) VALUES ",
+                "(32, 98, 41, 42, 'WhiteWins'), ",
+                "(33, 99, 41, 42, 'BlackWinsByDefault'), ",
+                "(34, 99, 42, 43, 'Jigo');"
+            ))
+            .unwrap();
+        trans.commit().unwrap();
+    }
+
+    fn current_ratings(conn: &rusqlite::Connection) -> Vec<f64> {
+        let mut stmt = conn
+            .prepare("SELECT currentrating FROM players ORDER BY id")
+            .unwrap();
+        let ratings = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        ratings
+    }
+
+    #[test]
+    fn update_ratings_1() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let ratings = current_ratings(&conn);
+        let adjw = SYS.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 1.0);
+        assert_eq!(ratings[0], 1000.0 + adjw);
+        assert!(ratings[1] < 1000.0);
+        assert!(ratings[2] > 1000.0);
+    }
+
+    #[test]
+    fn update_ratings_provisional() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let mut settings = load_settings(&conn).unwrap();
+        settings.provisional_games = 1;
+        settings.provisional_factor = 3.0;
+        save_settings(&conn, &settings).unwrap();
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let ratings = current_ratings(&conn);
+        let adjw = SYS.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 1.0);
+        let adjl = SYS.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 0.0);
+        assert_eq!(ratings[0], 1000.0 + 3.0 * adjw);
+        // Player 2 already played a rated game in the first round,
+        // player 3 plays their first rated game in the second round.
+        let r2 = Rating(1000.0 + 3.0 * adjl);
+        let adj2 = SYS.rating_adjustment(r2, Rating(1000.0), 0.0, 0.5);
+        let adj3 = SYS.rating_adjustment(Rating(1000.0), r2, 0.0, 0.5);
+        assert_eq!(ratings[1], r2.0 + adj2);
+        assert_eq!(ratings[2], 1000.0 + 3.0 * adj3);
+    }
+
+    #[test]
+    fn update_ratings_small_board() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        conn.execute(
+            "UPDATE games SET boardsize = 9, handicap = 1.0 WHERE id = 32",
+            [],
+        )
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let ratings = current_ratings(&conn);
+        let handicap = SYS.board_handicap(1.0, 9);
+        let weight = SYS.board_weight(9);
+        let adjw = SYS.rating_adjustment(Rating(1000.0), Rating(1000.0), -handicap, 1.0);
+        assert_eq!(ratings[0], 1000.0 + weight * adjw);
+        assert!(weight * adjw > 0.0);
+    }
+
+    #[test]
+    fn update_ratings_reverse_komi() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let handicap: Handicap = "reverse komi 6.5".parse().unwrap();
+        conn.execute(
+            "UPDATE games SET handicap = ?1 WHERE id = 32",
+            [handicap.to_f64()],
+        )
+        .unwrap();
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let ratings = current_ratings(&conn);
+        let adjw =
+            SYS.rating_adjustment(Rating(1000.0), Rating(1000.0), -handicap.effective(), 1.0);
+        assert_eq!(ratings[0], 1000.0 + adjw);
+    }
+
+    #[test]
+    fn update_ratings_max_drop_period() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(44, \"player4\", -500.0, -500.0), ",
+            "(45, \"player5\", -900.0, -900.0);",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(35, 98, 44, 45, 'BlackWins'), ",
+            "(36, 99, 44, 45, 'BlackWins');"
+        ))
+        .unwrap();
+        let rating_after = |conn: &mut rusqlite::Connection, settings: &Settings| {
+            save_settings(conn, settings).unwrap();
+            let trans = conn.transaction().unwrap();
+            update_ratings(&trans).unwrap();
+            trans.commit().unwrap();
+            current_ratings(conn)[3]
+        };
+        let mut settings = load_settings(&conn).unwrap();
+        // Each loss is limited separately, but neither reaches the limit.
+        let per_round = rating_after(&mut conn, &settings);
+        assert!(per_round < -600.0);
+        // Both rounds are in the same month, so both losses together are
+        // limited to 100 points.
+        settings.max_drop_period = RatingPeriod::Month;
+        assert_eq!(rating_after(&mut conn, &settings), -600.0);
+        settings.max_drop_period = RatingPeriod::Season;
+        settings.max_drop = Some(50.0);
+        assert_eq!(rating_after(&mut conn, &settings), -550.0);
+        settings.max_drop = None;
+        assert!(rating_after(&mut conn, &settings) < -600.0);
+    }
+
+    #[test]
+    fn update_ratings_max_drop_period_grouping() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        conn.execute_batch(concat!(
+            "INSERT INTO players (id, name, initialrating, currentrating) VALUES ",
+            "(44, \"player4\", -500.0, -500.0), ",
+            "(45, \"player5\", -900.0, -900.0);",
+            "INSERT INTO games (id, played, white, black, result) VALUES ",
+            "(35, 98, 44, 45, 'BlackWins'), ",
+            "(36, 99, 44, 45, 'BlackWins');"
+        ))
+        .unwrap();
+        let ratings_after = |conn: &mut rusqlite::Connection, settings: &Settings| {
+            save_settings(conn, settings).unwrap();
+            let trans = conn.transaction().unwrap();
+            update_ratings(&trans).unwrap();
+            trans.commit().unwrap();
+            current_ratings(conn)
+        };
+        let mut settings = load_settings(&conn).unwrap();
+        let per_round = ratings_after(&mut conn, &settings);
+        settings.max_drop_period = RatingPeriod::Month;
+        let per_month = ratings_after(&mut conn, &settings);
+        // The games are rated round by round either way, so only player 4,
+        // who reaches the limit within the month, differs.
+        assert_eq!(per_round[..3], per_month[..3]);
+        assert!(per_round[3] < per_month[3]);
+        assert_eq!(per_round[4], per_month[4]);
+        settings.max_drop = None;
+        let unlimited = ratings_after(&mut conn, &settings);
+        settings.max_drop_period = RatingPeriod::Round;
+        assert_eq!(ratings_after(&mut conn, &settings), unlimited);
+        assert_eq!(unlimited[3], per_round[3]);
+    }
+
+    #[test]
+    fn update_ratings_parameters() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let mut settings = load_settings(&conn).unwrap();
+        settings.bonus_factor = 0.0;
+        settings.min_rating = 990.0;
+        save_settings(&conn, &settings).unwrap();
+        conn.execute("DELETE FROM games WHERE id = 34", []).unwrap();
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let ratings = current_ratings(&conn);
+        let sys = settings.rating_system();
+        let adjw = sys.rating_adjustment(Rating(1000.0), Rating(1000.0), 0.0, 1.0);
+        assert_eq!(ratings[0], 1000.0 + adjw);
+        assert_eq!(ratings[1], 990.0);
+    }
+
+    #[test]
+    fn provisional_players_1() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let mut settings = load_settings(&conn).unwrap();
+        assert!(provisional_players(&conn, &settings).unwrap().is_empty());
+        settings.provisional_games = 2;
+        let expected: HashSet<i32> = [41, 43].iter().cloned().collect();
+        assert_eq!(provisional_players(&conn, &settings).unwrap(), expected);
+        settings.provisional_games = 3;
+        assert_eq!(provisional_players(&conn, &settings).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn performance_ratings_1() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let settings = load_settings(&conn).unwrap();
+        let games = rated_games(&conn, &settings).unwrap();
+        let performances = performance_ratings(&settings, &games);
+        // Player 1 won every game, so there is no performance rating.
+        assert_eq!(performances.len(), 2);
+        // Player 3 drew against player 2, who had lost a game before.
+        let r42 = games[1].delta.white_rating;
+        assert!(r42.0 < 1000.0);
+        assert!((performances[&43].0 - r42.0).abs() < 0.01);
+        assert!(performances[&42].0 < r42.0);
+        let performances = performance_ratings(&settings, &games[1..]);
+        assert!((performances[&42].0 - 1000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn stored_performance_ratings_1() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let trans = conn.transaction().unwrap();
+        update_ratings(&trans).unwrap();
+        trans.commit().unwrap();
+        let settings = load_settings(&conn).unwrap();
+        let rated = rated_games(&conn, &settings).unwrap();
+        let all: HashSet<i32> = [32, 33, 34].iter().copied().collect();
+        let stored = stored_performance_ratings(&conn, &settings, &all).unwrap();
+        let computed = performance_ratings(&settings, &rated);
+        assert_eq!(stored.len(), computed.len());
+        for (id, rating) in &computed {
+            assert_eq!(stored[id].0, rating.0);
+        }
+        // Only the selected games count.
+        let jigo: HashSet<i32> = [34].iter().copied().collect();
+        let stored = stored_performance_ratings(&conn, &settings, &jigo).unwrap();
+        assert!(stored.contains_key(&42));
+        assert!(stored.contains_key(&43));
+        assert!(!stored.contains_key(&41));
+    }
+
+    #[test]
+    fn rated_games_expected_legacy() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&mut conn);
+        let mut settings = load_settings(&conn).unwrap();
+        let games = rated_games(&conn, &settings).unwrap();
+        assert_eq!(games[0].black_expected + games[0].white_expected, 1.0);
+        // Part of the expected result goes to neither player.
+        settings.rating_formula = RatingFormula::EgdLegacy;
+        let games = rated_games(&conn, &settings).unwrap();
+        let total = games[0].black_expected + games[0].white_expected;
+        assert!((total - 0.984).abs() < 1e-9);
+    }
+}